@@ -0,0 +1,51 @@
+//! Compares parsing a configuration of 5000 rules with [`parse`], which compiles every regex
+//! right away, and with [`parse_lazy`], which compiles them on first access.
+//!
+//! Run with `cargo bench --bench lazy`.
+
+use mdev_parser::{parse, parse_lazy};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const RULES: usize = 5000;
+const ROUNDS: usize = 10;
+
+fn time(mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    start.elapsed() / ROUNDS as u32
+}
+
+fn main() {
+    let input: String = (0..RULES)
+        .map(|i| {
+            format!(
+                "SUBSYSTEM=block;dev{}([0-9]+)p[0-9]+ root:disk 660 =dev/{}/%1 @/bin/notify $MDEV\n",
+                i, i
+            )
+        })
+        .collect();
+
+    let eager = time(|| {
+        black_box(parse(&input));
+    });
+    let lazy = time(|| {
+        black_box(parse_lazy(&input));
+    });
+    let lazy_all = time(|| {
+        for conf in parse_lazy(&input) {
+            let _ = black_box(conf.get());
+        }
+    });
+
+    println!("{} rules", RULES);
+    println!("parse:                       {:?}", eager);
+    println!("parse_lazy:                  {:?}", lazy);
+    println!("parse_lazy, then every rule: {:?}", lazy_all);
+    println!(
+        "speedup when not accessed:   {:.1}x",
+        eager.as_secs_f64() / lazy.as_secs_f64()
+    );
+}
@@ -0,0 +1,55 @@
+//! Compares matching devices with a [`RuleSet`] against testing every rule in turn, on a
+//! configuration of 1000 device name rules.
+//!
+//! Run with `cargo bench --bench ruleset`.
+
+use mdev_parser::{first_match_index, parse, MatchContext, RuleSet};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const RULES: usize = 1000;
+const ROUNDS: usize = 20;
+
+fn time(mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    start.elapsed() / ROUNDS as u32
+}
+
+fn main() {
+    let input: String = (0..RULES)
+        .map(|i| format!("dev{}([0-9]+)p[0-9]+ root:disk 660 =dev/{}/%1\n", i, i))
+        .collect();
+    let confs = parse(&input);
+    let devices: Vec<_> = (0..RULES)
+        .step_by(10)
+        .chain([RULES + 1])
+        .map(|i| MatchContext {
+            name: format!("dev{}1p2", i),
+            devnum: None,
+            env: Default::default(),
+        })
+        .collect();
+
+    let rules = RuleSet::new(&confs);
+    let separate = time(|| {
+        for device in &devices {
+            black_box(first_match_index(&confs, device));
+        }
+    });
+    let combined = time(|| {
+        for device in &devices {
+            black_box(rules.first_match_index(device));
+        }
+    });
+
+    println!("{} devices, {} rules", devices.len(), RULES);
+    println!("each rule in turn: {:?}", separate);
+    println!("rule set:          {:?}", combined);
+    println!(
+        "speedup:           {:.1}x",
+        separate.as_secs_f64() / combined.as_secs_f64()
+    );
+}
@@ -0,0 +1,221 @@
+use crate::{without_default, Command, Conf, Filter, MatchContext, Phase};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, PartialEq)]
+/// What to do for a device, as decided by the winning rule, see [`resolve_action`]
+pub struct DeviceAction {
+    /// Index of the winning rule
+    pub rule: usize,
+    /// The device the action was resolved for
+    pub device: MatchContext,
+    /// Owner of the node
+    pub user: String,
+    /// Group of the node
+    pub group: String,
+    /// Permissions of the node
+    pub mode: u32,
+    /// Where the node is created, with `%0`–`%9` replaced, or [`None`] if its creation is
+    /// prevented
+    pub target_path: Option<PathBuf>,
+    /// The commands to run when the device is added, in the order of the rules
+    pub on_add: Vec<Command>,
+    /// The commands to run when the device is removed, in the order of the rules
+    pub on_remove: Vec<Command>,
+}
+
+/// Finds the rule deciding how the device described by `ctx` is created, like
+/// [`first_match_index`](crate::first_match_index), and gathers everything needed to handle
+/// the device.
+///
+/// Like mdev, every matching rule is applied up to the first one without the `-` prefix, so the
+/// commands of the rules applied before the winning one are run as well. The owner, the mode and
+/// the node come from the winning rule, which is applied last. The capture groups used for
+/// `%0`–`%9` come from the filter regex, matched against the device name or against the
+/// variable of a `$VAR=regex` filter. Returns [`None`] if no rule matches, in which case the
+/// node is created as `/dev/<name>` with the owner and mode of [`Conf::default()`], and no
+/// command is run.
+pub fn resolve_action(confs: &[Conf], ctx: &MatchContext) -> Option<DeviceAction> {
+    let mut applied = Vec::new();
+    for (i, conf) in without_default(confs).iter().enumerate() {
+        if conf.matches(ctx) {
+            applied.push((i, conf));
+            if conf.stop {
+                break;
+            }
+        }
+    }
+    let commands = |phase| {
+        applied
+            .iter()
+            .filter_map(|(_, conf)| conf.command_for(phase).cloned())
+            .collect()
+    };
+    let &(rule, conf) = applied.last()?;
+    let caps = match &conf.filter {
+        Filter::DeviceRegex(devregex) => {
+            let value = match &devregex.envvar {
+                Some(var) => ctx.env.get(var).map(String::as_str),
+                None => Some(ctx.name.as_str()),
+            };
+            value.and_then(|value| devregex.captures(value))
+        }
+        Filter::MajMin(_) | Filter::MajMinList(_) => None,
+    };
+    Some(DeviceAction {
+        rule,
+        device: ctx.clone(),
+        user: conf.user.clone(),
+        group: conf.group.clone(),
+        mode: conf.mode,
+        target_path: conf.target_path(&ctx.name, caps.as_ref()),
+        on_add: commands(Phase::Add),
+        on_remove: commands(Phase::Remove),
+    })
+}
+
+impl DeviceAction {
+    /// The environment the commands of the action run with, for showing what a helper receives.
+    ///
+    /// `base` is completed with the variables mdev sets for the device: `MDEV`, the device
+    /// name, `MAJOR` and `MINOR` if the device has numbers, and `ACTION`, `SUBSYSTEM` and
+    /// `DEVPATH` if they are in the environment of the uevent. These replace the values of
+    /// `base`, while the rest of the uevent is not added.
+    pub fn helper_env(&self, base: &HashMap<String, String>) -> HashMap<String, String> {
+        let mut env = base.clone();
+        env.insert("MDEV".into(), self.device.name.clone());
+        if let Some((maj, min)) = self.device.devnum {
+            env.insert("MAJOR".into(), maj.to_string());
+            env.insert("MINOR".into(), min.to_string());
+        }
+        for &var in &["ACTION", "SUBSYSTEM", "DEVPATH"] {
+            if let Some(value) = self.device.env.get(var) {
+                env.insert(var.into(), value.clone());
+            }
+        }
+        env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, WhenToRun};
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    #[test]
+    fn resolve() {
+        let confs = parse(
+            "\
+-sd[a-z].* root:disk 660 @/bin/log
+sd([a-z])([0-9]+) root:disk 640 >disk/%1/part%2 *disk-helper
+$DEVPATH=.*/(usb[0-9]+)/.* root:usb 660 =bus/%1
+null root:root 666 !
+",
+        );
+        let device = |name: &str, env: &[(&str, &str)]| MatchContext {
+            name: name.into(),
+            devnum: None,
+            env: env
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+        };
+
+        let action = resolve_action(&confs, &device("sda1", &[])).unwrap();
+        assert_eq!(action.rule, 1);
+        assert_eq!(
+            (action.user.as_str(), action.group.as_str()),
+            ("root", "disk")
+        );
+        assert_eq!(action.mode, 0o640);
+        assert_eq!(
+            action.target_path.as_deref(),
+            Some(Path::new("/dev/disk/a/part1"))
+        );
+        // The command of the `-` rule applied before the winning one runs too, first
+        let on_add: Vec<_> = action
+            .on_add
+            .iter()
+            .map(|c| (c.when.clone(), c.path.as_str()))
+            .collect();
+        assert_eq!(
+            on_add,
+            [
+                (WhenToRun::After, "/bin/log"),
+                (WhenToRun::Both, "disk-helper")
+            ]
+        );
+        assert_eq!(action.on_remove.len(), 1);
+        assert_eq!(action.on_remove[0].path, "disk-helper");
+
+        let usb = device("hiddev0", &[("DEVPATH", "/devices/pci0/usb1/1-1")]);
+        let action = resolve_action(&confs, &usb).unwrap();
+        assert_eq!(action.rule, 2);
+        assert_eq!(
+            action.target_path.as_deref(),
+            Some(Path::new("/dev/bus/usb1"))
+        );
+        assert!(action.on_add.is_empty() && action.on_remove.is_empty());
+
+        let action = resolve_action(&confs, &device("null", &[])).unwrap();
+        assert_eq!(action.target_path, None);
+
+        // The rule with the `-` prefix only wins when no other rule matches
+        let action = resolve_action(&confs, &device("sdb", &[])).unwrap();
+        assert_eq!(action.rule, 0);
+        assert_eq!(action.on_add.len(), 1);
+        assert_eq!(action.on_add[0].path, "/bin/log");
+        assert!(action.on_remove.is_empty());
+
+        assert_eq!(resolve_action(&confs, &device("tty0", &[])), None);
+    }
+
+    #[test]
+    fn helper_env() {
+        let confs = parse("sd[a-z][0-9]* root:disk 660 */bin/disk-helper\n");
+        let uevent: HashMap<_, _> = [
+            ("ACTION", "add"),
+            ("DEVNAME", "sda1"),
+            ("DEVPATH", "/devices/pci0/host0/block/sda/sda1"),
+            ("SUBSYSTEM", "block"),
+            ("MAJOR", "8"),
+            ("MINOR", "1"),
+            ("SEQNUM", "1234"),
+        ]
+        .iter()
+        .map(|&(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let action = resolve_action(&confs, &MatchContext::from_uevent(uevent)).unwrap();
+        let base: HashMap<_, _> = [("PATH", "/sbin:/bin"), ("MDEV", "stale")]
+            .iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let env = action.helper_env(&base);
+        let mut vars: Vec<_> = env.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        vars.sort();
+        assert_eq!(
+            vars,
+            [
+                ("ACTION", "add"),
+                ("DEVPATH", "/devices/pci0/host0/block/sda/sda1"),
+                ("MAJOR", "8"),
+                ("MDEV", "sda1"),
+                ("MINOR", "1"),
+                ("PATH", "/sbin:/bin"),
+                ("SUBSYSTEM", "block"),
+            ]
+        );
+
+        // A device found by its name alone only gets its name
+        let ctx = MatchContext {
+            name: "sdb".into(),
+            ..MatchContext::default()
+        };
+        let action = resolve_action(&confs, &ctx).unwrap();
+        let env = action.helper_env(&HashMap::new());
+        assert_eq!(env.len(), 1);
+        assert_eq!(env["MDEV"], "sdb");
+    }
+}
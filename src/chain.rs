@@ -0,0 +1,215 @@
+use crate::{without_default, Conf, Filter, MajMin};
+use regex::Regex;
+use regex_syntax::hir::{Class, Hir, HirKind};
+use std::fmt::{self, Display};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A continue rule (`-`) after which no rule stops the search, found by
+/// [`lint_no_terminal_stop`]
+pub struct StopWarning {
+    /// Index of the continue rule
+    pub rule: usize,
+    /// The device the rule was checked with, like `sda`, `@8,0` or `$MODALIAS=usb`
+    pub device: String,
+}
+
+impl Display for StopWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rule {}: no rule stopping the search matches `{}` after it",
+            self.rule, self.device
+        )
+    }
+}
+
+impl std::error::Error for StopWarning {}
+
+/// A device matched by the filter of a rule
+enum Device {
+    Name(String),
+    Var(String, String),
+    Number(u32, u32),
+}
+
+impl Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Device::Name(name) => write!(f, "{}", name),
+            Device::Var(var, value) => write!(f, "${}={}", var, value),
+            Device::Number(maj, min) => write!(f, "@{},{}", maj, min),
+        }
+    }
+}
+
+impl Device {
+    fn of(filter: &Filter) -> Option<Self> {
+        match filter {
+            Filter::DeviceRegex(devregex) => {
+                let value = example(&devregex.regex)?;
+                Some(match &devregex.envvar {
+                    Some(var) => Device::Var(var.clone(), value),
+                    None => Device::Name(value),
+                })
+            }
+            Filter::MajMin(majmin) => Some(Device::Number(majmin.maj, majmin.min)),
+            Filter::MajMinList(list) => list.first().map(|m| Device::Number(m.maj, m.min)),
+        }
+    }
+
+    /// Whether a rule with `filter` can apply to the device. What is not known about the device,
+    /// like the numbers of a device found by its name, is assumed to match.
+    fn may_match(&self, filter: &Filter) -> bool {
+        let contains = |majmin: &MajMin, maj: u32, min: u32| majmin.contains((maj, min));
+        match (self, filter) {
+            (Device::Name(name), Filter::DeviceRegex(devregex)) if devregex.envvar.is_none() => {
+                devregex.captures(name).is_some()
+            }
+            (Device::Var(var, value), Filter::DeviceRegex(devregex))
+                if devregex.envvar.as_ref() == Some(var) =>
+            {
+                devregex.captures(value).is_some()
+            }
+            (&Device::Number(maj, min), Filter::MajMin(majmin)) => contains(majmin, maj, min),
+            (&Device::Number(maj, min), Filter::MajMinList(list)) => {
+                list.iter().any(|majmin| contains(majmin, maj, min))
+            }
+            _ => true,
+        }
+    }
+}
+
+/// A short string matched by the whole `regex`, or [`None`] if there is none or it cannot be
+/// found
+fn example(regex: &Regex) -> Option<String> {
+    let hir = regex_syntax::parse(regex.as_str()).ok()?;
+    let mut example = String::new();
+    push_example(&hir, &mut example).then_some(example)
+}
+
+fn push_example(hir: &Hir, out: &mut String) -> bool {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => true,
+        HirKind::Literal(literal) => match std::str::from_utf8(&literal.0) {
+            Ok(literal) => {
+                out.push_str(literal);
+                true
+            }
+            Err(_) => false,
+        },
+        HirKind::Class(Class::Unicode(class)) => {
+            // Prefer the characters found in device names, rather than the first one of `.`
+            let ranges = class.ranges();
+            let preferred = ['a', '0', 'A', '_', '-', '/'];
+            let c = preferred
+                .iter()
+                .copied()
+                .find(|&c| ranges.iter().any(|r| r.start() <= c && c <= r.end()))
+                .or_else(|| ranges.first().map(|r| r.start()));
+            c.map(|c| out.push(c)).is_some()
+        }
+        HirKind::Class(Class::Bytes(class)) => {
+            let c = class
+                .ranges()
+                .first()
+                .map(|r| r.start())
+                .filter(u8::is_ascii);
+            c.map(|c| out.push(char::from(c))).is_some()
+        }
+        HirKind::Repetition(repetition) => {
+            // Repeat optional parts once, for names like `sda0` rather than `sd` for
+            // `sd[a-z]*[0-9]*`, since device names are never empty
+            let count = match repetition.max {
+                Some(0) => 0,
+                _ => repetition.min.max(1),
+            };
+            (0..count).all(|_| push_example(&repetition.sub, out))
+        }
+        HirKind::Capture(capture) => push_example(&capture.sub, out),
+        HirKind::Concat(hirs) => hirs.iter().all(|hir| push_example(hir, out)),
+        HirKind::Alternation(hirs) => {
+            let len = out.len();
+            hirs.iter().any(|hir| {
+                out.truncate(len);
+                push_example(hir, out)
+            })
+        }
+    }
+}
+
+/// Warns about the continue rules (`-`) after which no rule stops the search for the devices
+/// they match, so that the search goes on until the trailing [`Conf::default()`]. This is
+/// sometimes meant, but usually a stopping rule is missing or comes too early.
+///
+/// Each continue rule is checked with a device it matches, like `sda0` for `sd[a-z][0-9]+`,
+/// against the filters of the stopping rules after it. The envmatches are not evaluated, and a
+/// filter testing something else than the filter of the continue rule, like a majmin filter
+/// after a device name regex, is assumed to match, so only the chains that clearly end without
+/// a stopping rule are reported. Rules whose device regex matches nothing are skipped.
+pub fn lint_no_terminal_stop(confs: &[Conf]) -> Vec<StopWarning> {
+    let confs = without_default(confs);
+    confs
+        .iter()
+        .enumerate()
+        .filter(|(_, conf)| !conf.stop)
+        .filter_map(|(i, conf)| {
+            let device = Device::of(&conf.filter)?;
+            let stopped = confs[i + 1..]
+                .iter()
+                .any(|later| later.stop && device.may_match(&later.filter));
+            (!stopped).then(|| StopWarning {
+                rule: i,
+                device: device.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn terminal_stop() {
+        let confs = parse(
+            "\
+-sd[a-z][0-9]+ root:disk 660 @/bin/log-disk
+sd[a-z]+[0-9]* root:disk 660
+-(tty|console) root:tty 620 @/bin/log-tty
+-null root:root 666
+tty root:tty 620
+",
+        );
+        let warnings = lint_no_terminal_stop(&confs);
+        assert_eq!(
+            warnings,
+            [StopWarning {
+                rule: 3,
+                device: "null".into()
+            }]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "rule 3: no rule stopping the search matches `null` after it"
+        );
+
+        let device = |input| {
+            let warnings = lint_no_terminal_stop(&parse(input));
+            warnings.into_iter().map(|w| w.device).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            device("-@8,0-15 root:disk 660\n@8,16-31 root:disk 660\n"),
+            ["@8,0"]
+        );
+        assert!(device("-@8,0-15 root:disk 660\n@8,0 root:disk 660\n").is_empty());
+        assert_eq!(
+            device("-$MODALIAS=pci:.* root:root 660\n$MODALIAS=usb:.* root:root 660\n"),
+            ["$MODALIAS=pci:a"]
+        );
+        // A filter on something else may match the same device
+        assert!(device("-$MODALIAS=pci:.* root:root 660\nsd[a-z] root:disk 660\n").is_empty());
+        assert_eq!(device("-(sd|hd)[a-z]? root:disk 660\n"), ["sda"]);
+        assert!(device("-[^\\s\\S] root:root 600\n").is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{Conf, Filter, MajMin};
+
+/// The range covering `a` and `b`, if `b` continues `a` or the other way round. Empty ranges,
+/// like `@8,5-0`, are never joined.
+fn join(a: &MajMin, b: &MajMin) -> Option<MajMin> {
+    let a_end = a.min2.unwrap_or(a.min);
+    let b_end = b.min2.unwrap_or(b.min);
+    if a.min > a_end || b.min > b_end {
+        return None;
+    }
+    let (min, min2) = if a_end.checked_add(1) == Some(b.min) {
+        (a.min, b_end)
+    } else if b_end.checked_add(1) == Some(a.min) {
+        (b.min, a_end)
+    } else {
+        return None;
+    };
+    (a.maj == b.maj).then_some(MajMin {
+        maj: a.maj,
+        min,
+        min2: Some(min2),
+    })
+}
+
+/// The filter of the rule matching the devices of both `a` and `b`, if they only differ in
+/// contiguous majmin ranges
+fn merged(a: &Conf, b: &Conf) -> Option<MajMin> {
+    let same = a.stop == b.stop
+        && a.envmatches == b.envmatches
+        && a.user == b.user
+        && a.group == b.group
+        && a.mode == b.mode
+        && a.on_creation == b.on_creation
+        && a.command == b.command
+        && a.label == b.label;
+    match (&a.filter, &b.filter) {
+        (Filter::MajMin(a), Filter::MajMin(b)) if same => join(a, b),
+        _ => None,
+    }
+}
+
+/// Merges consecutive majmin rules that only differ in their contiguous minor ranges, like
+/// `@8,0` and `@8,1-3`, into a single range rule like `@8,0-3`.
+///
+/// Each device matched by either rule is matched by the merged one, which applies the same
+/// settings, so this does not change how any device is handled. The order of the rules is kept,
+/// and rules with different labels are never merged.
+pub fn coalesce_majmin(confs: &mut Vec<Conf>) {
+    let mut coalesced: Vec<Conf> = Vec::with_capacity(confs.len());
+    for conf in confs.drain(..) {
+        if let Some(last) = coalesced.last_mut() {
+            if let Some(majmin) = merged(last, &conf) {
+                last.filter = majmin.into();
+                continue;
+            }
+        }
+        coalesced.push(conf);
+    }
+    *confs = coalesced;
+}
+
+/// Whether some device is matched by both `a` and `b`
+fn overlap(a: &MajMin, b: &MajMin) -> bool {
+    let a_end = a.min2.unwrap_or(a.min);
+    let b_end = b.min2.unwrap_or(b.min);
+    a.maj == b.maj && a.min <= a_end && b.min <= b_end && a.min <= b_end && b.min <= a_end
+}
+
+/// The majmin ranges of the filter of `conf`, if it is a majmin filter
+fn ranges(conf: &Conf) -> &[MajMin] {
+    match &conf.filter {
+        Filter::MajMin(majmin) => std::slice::from_ref(majmin),
+        Filter::MajMinList(list) => list,
+        Filter::DeviceRegex(_) => &[],
+    }
+}
+
+/// Returns the pairs of indices of the majmin rules matching some device numbers in common,
+/// like `@8,0-15` and `@8,8-31`, with the first index lower than the second, in order.
+///
+/// Only the device numbers are compared, and a list like `@8,0 @9,0` overlaps a rule if any of
+/// its ranges does. Empty ranges, like `@8,16-0`, never overlap.
+pub fn find_majmin_overlaps(confs: &[Conf]) -> Vec<(usize, usize)> {
+    let mut overlaps = Vec::new();
+    for (i, a) in confs.iter().enumerate() {
+        for (j, b) in confs.iter().enumerate().skip(i + 1) {
+            let any = ranges(a)
+                .iter()
+                .any(|a| ranges(b).iter().any(|b| overlap(a, b)));
+            if any {
+                overlaps.push((i, j));
+            }
+        }
+    }
+    overlaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_strict, to_config_string};
+
+    fn coalesced(input: &str) -> String {
+        let mut confs = parse_strict(input).unwrap();
+        coalesce_majmin(&mut confs);
+        to_config_string(&confs)
+    }
+
+    #[test]
+    fn coalesce() {
+        assert_eq!(
+            coalesced("@8,0 root:disk 660\n@8,1 root:disk 660\n@8,2-4 root:disk 660\n"),
+            "@8,0-4 root:disk 660\n"
+        );
+        assert_eq!(
+            coalesced("@8,2 root:disk 660\n@8,0-1 root:disk 660\nsda root:disk 660\n"),
+            "@8,0-2 root:disk 660\nsda root:disk 660\n"
+        );
+
+        // Gaps, other majors, other settings and rules in between
+        let untouched = "\
+@8,0 root:disk 660
+@8,2 root:disk 660
+@9,3 root:disk 660
+@9,4 root:disk 600
+@9,5 root:disk 600 =disk/
+-@9,6 root:disk 600 =disk/
+SUBSYSTEM=block;@9,7 root:disk 600 =disk/
+sda root:disk 660
+@9,8 root:disk 660
+@9,10-0 root:disk 660
+@9,1-9 root:disk 660
+";
+        assert_eq!(coalesced(untouched), untouched);
+
+        // Merging rules with different labels would lose one of them
+        let mut confs = parse_strict("@8,0 root:disk 660\n@8,1 root:disk 660\n").unwrap();
+        confs[0].label = Some("a".into());
+        confs[1].label = Some("b".into());
+        let labeled = confs.clone();
+        coalesce_majmin(&mut confs);
+        assert_eq!(confs, labeled);
+        confs[1].label = Some("a".into());
+        coalesce_majmin(&mut confs);
+        assert_eq!(confs[0].filter, MajMin::with_count(8, 0, 2).into());
+        assert_eq!(confs[0].label.as_deref(), Some("a"));
+        assert_eq!(coalesced(""), "");
+    }
+
+    #[test]
+    fn overlaps() {
+        let confs = parse_strict(
+            "\
+@8,0-15 root:disk 660
+@8,16-31 root:disk 660
+@9,0-15 root:disk 660
+sda root:disk 660
+@8,8 root:disk 600
+@8,31-0 root:disk 660
+@8,31 root:disk 640
+",
+        )
+        .unwrap();
+        assert_eq!(find_majmin_overlaps(&confs), [(0, 4), (1, 6)]);
+
+        let list = vec![MajMin::with_count(9, 32, 1), MajMin::with_count(8, 40, 8)];
+        let mut confs = confs[1..3].to_vec();
+        confs.push(confs[0].clone().with_filter(Filter::MajMinList(list)));
+        assert_eq!(find_majmin_overlaps(&confs), []);
+        confs.push(
+            confs[0]
+                .clone()
+                .with_filter(MajMin::with_count(8, 47, 1).into()),
+        );
+        assert_eq!(find_majmin_overlaps(&confs), [(2, 3)]);
+        assert_eq!(find_majmin_overlaps(&[]), []);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::{label::label_of, stream::BOM, Conf, ParseError, ParseEvent, StreamParser};
+
+#[derive(Clone, Debug, PartialEq)]
+/// A line of the configuration, as returned by [`parse_with_comments`]
+// Rules are the common case, boxing them would only add an allocation per line
+#[allow(clippy::large_enum_variant)]
+pub enum ConfLine {
+    /// A rule
+    Rule(Conf),
+    /// A comment, including the indentation before the `#` but without trailing whitespace
+    Comment(String),
+    /// A line containing only whitespace
+    Blank,
+}
+
+impl From<Conf> for ConfLine {
+    fn from(v: Conf) -> Self {
+        Self::Rule(v)
+    }
+}
+
+/// Parses every line of the configuration, keeping comments and blank lines in their position.
+///
+/// Unlike [`parse`](crate::parse), no default rule is appended, so the result can be modified
+/// and written back with [`render`]. Fails on the first invalid line.
+pub fn parse_with_comments(input: &str) -> Result<Vec<ConfLine>, ParseError> {
+    let mut parser = StreamParser::new();
+    let mut lines = Vec::new();
+    for line in input.strip_prefix(BOM).unwrap_or(input).lines() {
+        let line = match parser.feed(line) {
+            ParseEvent::Rule(conf) => ConfLine::Rule(conf),
+            ParseEvent::Comment(_) => ConfLine::Comment(line.trim_end().into()),
+            ParseEvent::Blank => ConfLine::Blank,
+            ParseEvent::Error(err) => return Err(err),
+            // The rule is added with its last line
+            ParseEvent::Continued => continue,
+        };
+        lines.push(line);
+    }
+    match parser.finish() {
+        Some(ParseEvent::Rule(conf)) => lines.push(ConfLine::Rule(conf)),
+        Some(ParseEvent::Error(err)) => return Err(err),
+        _ => {}
+    }
+    Ok(lines)
+}
+
+/// The label given by `line` to the rule after it, if it is a directive comment
+fn directive(line: Option<&ConfLine>) -> Option<&str> {
+    match line {
+        Some(ConfLine::Comment(comment)) => label_of(comment.trim_start()),
+        _ => None,
+    }
+}
+
+/// Formats the lines back into a configuration, one per line.
+///
+/// Comments and blank lines are kept as they were, while rules are written in the canonical
+/// form of [`Display`](std::fmt::Display). The comment giving its label to a rule is kept as
+/// well, unless the label of the rule was changed or removed: then the comment is left out, and
+/// the rule writes its own label, if any.
+pub fn render(lines: &[ConfLine]) -> String {
+    let mut out = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        match line {
+            ConfLine::Rule(conf) => {
+                // Written by the comment before the rule
+                let labeled = i > 0 && directive(lines.get(i - 1)) == conf.label.as_deref();
+                if labeled && conf.label.is_some() {
+                    out.push_str(
+                        &Conf {
+                            label: None,
+                            ..conf.clone()
+                        }
+                        .to_string(),
+                    );
+                } else {
+                    out.push_str(&conf.to_string());
+                }
+            }
+            ConfLine::Comment(comment) => {
+                if let (Some(label), Some(ConfLine::Rule(conf))) =
+                    (directive(Some(line)), lines.get(i + 1))
+                {
+                    if conf.label.as_deref() != Some(label) {
+                        continue;
+                    }
+                }
+                out.push_str(comment);
+            }
+            ConfLine::Blank => {}
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+# mdev.conf
+
+# Devices:
+null root:root 666
+zero root:root 666
+
+  # Block devices
+sd[a-z].* root:disk 660 */lib/mdev/usbdisk_link
+-SUBSYSTEM=net;.* root:root 600 @/lib/mdev/net-up
+";
+
+    #[test]
+    fn round_trip() {
+        let lines = parse_with_comments(CONFIG).unwrap();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[1], ConfLine::Blank);
+        assert_eq!(lines[6], ConfLine::Comment("  # Block devices".into()));
+        assert_eq!(render(&lines), CONFIG);
+    }
+
+    #[test]
+    fn labels() {
+        let config = "# mdev.conf\n  #name:   disks\nsd[a-z] root:disk 660\nnull root:root 666\n";
+        let lines = parse_with_comments(config).unwrap();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[1], ConfLine::Comment("  #name:   disks".into()));
+        assert_eq!(render(&lines), config);
+
+        #[cfg(feature = "extensions")]
+        {
+            let mut lines = lines;
+            let label = |lines: &mut [ConfLine], i: usize, label: Option<&str>| {
+                if let ConfLine::Rule(conf) = &mut lines[i] {
+                    conf.label = label.map(Into::into);
+                }
+            };
+            label(&mut lines, 2, Some("usb-disks"));
+            label(&mut lines, 3, Some("null"));
+            assert_eq!(
+                render(&lines),
+                "# mdev.conf\n#name: usb-disks\nsd[a-z] root:disk 660\n#name: null\nnull root:root 666\n"
+            );
+            label(&mut lines, 2, None);
+            assert_eq!(
+                render(&lines),
+                "# mdev.conf\nsd[a-z] root:disk 660\n#name: null\nnull root:root 666\n"
+            );
+        }
+    }
+
+    #[test]
+    fn rewrite_rules() {
+        let mut lines = parse_with_comments(CONFIG).unwrap();
+        for line in &mut lines {
+            if let ConfLine::Rule(conf) = line {
+                conf.mode = 0o600;
+            }
+        }
+        let rendered = render(&lines);
+        assert_eq!(
+            rendered.lines().collect::<Vec<_>>(),
+            [
+                "# mdev.conf",
+                "",
+                "# Devices:",
+                "null root:root 600",
+                "zero root:root 600",
+                "",
+                "  # Block devices",
+                "sd[a-z].* root:disk 600 */lib/mdev/usbdisk_link",
+                "-SUBSYSTEM=net;.* root:root 600 @/lib/mdev/net-up",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_line() {
+        let err = parse_with_comments("# ok\nsd[a-z root:disk 660\n").unwrap_err();
+        assert_eq!(err.line, 2);
+    }
+}
@@ -0,0 +1,109 @@
+//! Rules wrapped across several lines by ending them with `\`, with the `extensions` feature.
+//!
+//! The `\` is removed and the next line is appended as it is, so `@cmd arg1 \` followed by
+//! `  arg2` is parsed as `@cmd arg1   arg2`. Comments cannot be continued, and the lines of a
+//! rule are reported as the first one in errors.
+
+use std::borrow::Cow;
+
+#[derive(Clone, Debug, Default)]
+/// The beginning of a rule continued on the next lines
+pub(crate) struct Continuation {
+    /// Number of the first line of the rule, and the text joined so far
+    pending: Option<(usize, String)>,
+}
+
+impl Continuation {
+    /// Whether the previous lines are waiting for the rest of the rule
+    pub(crate) fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Adds the line numbered `line_number`, returning the whole rule with the number of its
+    /// first line, or [`None`] if it continues on the next line. Lines starting with `#` or
+    /// `comment_char` are comments.
+    pub(crate) fn push<'a>(
+        &mut self,
+        line_number: usize,
+        line: &'a str,
+        comment_char: char,
+    ) -> Option<(usize, Cow<'a, str>)> {
+        let comment = line.trim_start().starts_with(&['#', comment_char][..]);
+        let continued = line
+            .strip_suffix('\\')
+            .filter(|_| cfg!(feature = "extensions") && (self.is_pending() || !comment));
+        match (continued, self.pending.take()) {
+            (Some(start), Some((first, mut joined))) => {
+                joined.push_str(start);
+                self.pending = Some((first, joined));
+                None
+            }
+            (Some(start), None) => {
+                self.pending = Some((line_number, start.into()));
+                None
+            }
+            (None, Some((first, mut joined))) => {
+                joined.push_str(line);
+                Some((first, joined.into()))
+            }
+            (None, None) => Some((line_number, line.into())),
+        }
+    }
+
+    /// Returns the rule still waiting for its last line at the end of the input
+    pub(crate) fn finish(&mut self) -> Option<(usize, String)> {
+        self.pending.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_strict;
+
+    #[test]
+    fn continued_command() {
+        let input = "\
+# helpers \\
+sd[a-z] root:disk 660 @/sbin/mount-helper --fs=ext4 \\
+  --options=ro,noexec \\
+\t/mnt/disk
+null root:root 666
+sd[a-z] root:disk 66\\
+0 *bad-\\
+";
+        let res = parse_strict(input);
+        #[cfg(feature = "extensions")]
+        {
+            use crate::{parse_with_comments, render, ConfLine, ParseErrorKind};
+
+            let conf = res.unwrap();
+            let command = conf[0].command.as_ref().unwrap();
+            assert_eq!(command.path, "/sbin/mount-helper");
+            assert_eq!(
+                command.args,
+                ["--fs=ext4", "--options=ro,noexec", "/mnt/disk"]
+            );
+            assert_eq!(conf[1].to_string(), "null root:root 666");
+            assert_eq!(conf[2].command.as_ref().unwrap().path, "bad-");
+            assert_eq!(conf[2].mode, 0o660);
+
+            let err = parse_strict("null root:root 666\nsd[a-z root:disk \\\n660\n").unwrap_err();
+            assert_eq!(err.line, 2);
+            assert!(matches!(err.kind, ParseErrorKind::Regex(_)));
+
+            let lines = parse_with_comments(input).unwrap();
+            assert_eq!(lines[0], ConfLine::Comment("# helpers \\".into()));
+            assert_eq!(lines.len(), 4);
+            assert_eq!(
+                render(&lines[1..2]),
+                "sd[a-z] root:disk 660 @/sbin/mount-helper --fs=ext4 --options=ro,noexec /mnt/disk\n"
+            );
+        }
+        #[cfg(not(feature = "extensions"))]
+        {
+            // Without the feature the `\` is an argument, and the next line is a rule on its own
+            let err = res.unwrap_err();
+            assert_eq!(err.line, 3);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::Conf;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// Differences between two versions of a configuration, see [`diff`]
+pub struct ConfDiff {
+    /// Rules only present in the new configuration
+    pub added: Vec<Conf>,
+    /// Rules only present in the old configuration
+    pub removed: Vec<Conf>,
+    /// Rules matching the same devices, but doing something different, as `(old, new)`
+    pub changed: Vec<(Conf, Conf)>,
+}
+
+impl ConfDiff {
+    /// Whether the two configurations contain the same rules
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Conf {
+    /// Names of the fields that differ between `self` and `other`
+    pub fn changed_fields(&self, other: &Conf) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        if self.stop != other.stop {
+            fields.push("stop");
+        }
+        if self.envmatches != other.envmatches {
+            fields.push("envmatches");
+        }
+        if self.filter != other.filter {
+            fields.push("filter");
+        }
+        if self.user != other.user {
+            fields.push("user");
+        }
+        if self.group != other.group {
+            fields.push("group");
+        }
+        if self.mode != other.mode {
+            fields.push("mode");
+        }
+        if self.on_creation != other.on_creation {
+            fields.push("on_creation");
+        }
+        if self.command != other.command {
+            fields.push("command");
+        }
+        fields
+    }
+}
+
+/// Computes which rules were added, removed or changed between `old` and `new`.
+///
+/// Identical rules are paired first, regardless of their position. The remaining rules are
+/// considered changed when they have the same envmatches and filter, meaning that they apply to
+/// the same devices. Every rule is paired at most once.
+pub fn diff(old: &[Conf], new: &[Conf]) -> ConfDiff {
+    let mut old_used = vec![false; old.len()];
+    let mut new_used = vec![false; new.len()];
+    let mut changed = Vec::new();
+
+    let mut pair = |same: fn(&Conf, &Conf) -> bool| {
+        for (n, new_conf) in new.iter().enumerate() {
+            if new_used[n] {
+                continue;
+            }
+            let found = (0..old.len()).find(|&o| !old_used[o] && same(&old[o], new_conf));
+            if let Some(o) = found {
+                old_used[o] = true;
+                new_used[n] = true;
+                if old[o] != *new_conf {
+                    changed.push((old[o].clone(), new_conf.clone()));
+                }
+            }
+        }
+    };
+    pair(|a, b| a == b);
+    pair(|a, b| a.envmatches == b.envmatches && a.filter == b.filter);
+
+    let unused = |confs: &[Conf], used: &[bool]| {
+        confs
+            .iter()
+            .zip(used)
+            .filter(|(_, used)| !**used)
+            .map(|(conf, _)| conf.clone())
+            .collect()
+    };
+    ConfDiff {
+        added: unused(new, &new_used),
+        removed: unused(old, &old_used),
+        changed,
+    }
+}
+
+/// Whether `a` and `b` contain the same rules, each the same number of times, regardless of
+/// their order.
+///
+/// Rules are compared with the equality of [`Conf`], so regexes that differ only in how they
+/// are escaped are equal. This tells that no rule was added or dropped, not that the two
+/// configurations behave the same: the first matching rule that stops wins, so reordering rules
+/// can change which one applies to a device.
+pub fn rule_sets_equivalent(a: &[Conf], b: &[Conf]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut used = vec![false; b.len()];
+    a.iter().all(|conf| {
+        let found = (0..b.len()).find(|&i| !used[i] && b[i] == *conf);
+        found.map(|i| used[i] = true).is_some()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn diff_configs() {
+        let old = parse(
+            "sda root:disk 660\nSUBSYSTEM=net;.* root:root 600\nSUBSYSTEM=sound;.* root:audio 660\nnull root:root 666\n",
+        );
+        let new = parse(
+            "null   root:root 666\nSUBSYSTEM=sound;.* root:audio 640 @/bin/beep\nsda root:disk 660\ntty root:tty 620\n",
+        );
+        let d = diff(&old, &new);
+        assert_eq!(d.added, [new[3].clone()]);
+        assert_eq!(d.removed, [old[1].clone()]);
+        assert_eq!(d.changed, [(old[2].clone(), new[1].clone())]);
+        assert_eq!(
+            d.changed[0].0.changed_fields(&d.changed[0].1),
+            ["mode", "command"]
+        );
+
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn equivalent() {
+        let a = parse("sda root:disk 660\nnull root:root 666\nnull root:root 666\n");
+        let b = parse("null  root:root 666\nsda root:disk 660\nnull root:root 666\n");
+        assert!(rule_sets_equivalent(&a, &b));
+        assert!(rule_sets_equivalent(&b, &a));
+
+        let dropped = parse("sda root:disk 660\nnull root:root 666\n");
+        assert!(!rule_sets_equivalent(&a, &dropped));
+        let duplicated = parse("sda root:disk 660\nsda root:disk 660\nnull root:root 666\n");
+        assert!(!rule_sets_equivalent(&a, &duplicated));
+        assert!(rule_sets_equivalent(&[], &[]));
+    }
+}
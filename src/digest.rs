@@ -0,0 +1,66 @@
+use crate::Conf;
+use std::borrow::Cow;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// A digest of the rules, for telling whether a configuration changed, like when deciding to
+/// reload it.
+///
+/// Each rule is hashed in order through its canonical form, the one written by its
+/// [`Display`](std::fmt::Display) implementation, so comments, whitespace and the way regexes
+/// are escaped do not change the digest. Labels do not either, since mdev reads them as plain
+/// comments.
+///
+/// The digest is the 64-bit FNV-1a hash of the canonical lines, which does not depend on the
+/// platform or on the version of Rust, so it can be stored. It is not a cryptographic hash.
+pub fn rule_set_digest(confs: &[Conf]) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    for conf in confs {
+        let conf = match conf.label {
+            Some(_) => Cow::Owned(Conf {
+                label: None,
+                ..conf.clone()
+            }),
+            None => Cow::Borrowed(conf),
+        };
+        for byte in conf.to_string().bytes().chain(Some(b'\n')) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn digest() {
+        let confs = parse("sd[a-z] root:disk 660\nnull root:root 666 @/bin/notify $MDEV\n");
+        let reformatted = parse(
+            "\
+# disks
+  sd[a-z]\troot:disk   660
+
+null root:root 666 @/bin/notify $MDEV
+",
+        );
+        assert_eq!(rule_set_digest(&confs), rule_set_digest(&reformatted));
+        // Digests can be stored, so they must never change
+        assert_eq!(rule_set_digest(&[]), FNV_OFFSET_BASIS);
+        let single = &parse("sda root:disk 660")[..1];
+        assert_eq!(rule_set_digest(single), 0xa523_4ae2_945a_a5c0);
+
+        let reordered = parse("null root:root 666 @/bin/notify $MDEV\nsd[a-z] root:disk 660\n");
+        assert_ne!(rule_set_digest(&confs), rule_set_digest(&reordered));
+        let changed = parse("sd[a-z] root:disk 640\nnull root:root 666 @/bin/notify $MDEV\n");
+        assert_ne!(rule_set_digest(&confs), rule_set_digest(&changed));
+
+        let mut labeled = confs.clone();
+        labeled[0].label = Some("disks".into());
+        assert_eq!(rule_set_digest(&confs), rule_set_digest(&labeled));
+    }
+}
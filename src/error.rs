@@ -0,0 +1,204 @@
+use crate::Rule;
+use pest::error::InputLocation;
+use std::{fmt, io, num::ParseIntError, ops::Range, str::Utf8Error};
+
+#[derive(Clone, Debug, PartialEq)]
+/// An error found while parsing a line of the configuration
+pub struct ParseError {
+    /// Number of the line, starting from 1
+    pub line: usize,
+    /// Byte range of the offending token inside the line
+    pub span: Range<usize>,
+    /// Content of the line
+    pub source_line: String,
+    /// What went wrong
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// The kind of a [`ParseError`]
+pub enum ParseErrorKind {
+    /// The line does not respect the grammar
+    Grammar(String),
+    /// The regex is not valid
+    Regex(regex::Error),
+    /// A number is too big
+    Number(ParseIntError),
+    /// The mode contains a digit that is not octal
+    OctalDigit(char),
+    /// A major or minor number is above the limits of the kernel
+    DeviceNumber {
+        /// The number found in the configuration
+        value: u32,
+        /// The largest number accepted by the kernel
+        max: u32,
+    },
+    /// The line uses an extension of the syntax, which requires the `extensions` feature
+    Extension(&'static str),
+    /// The line is not valid UTF-8
+    Utf8(Utf8Error),
+    /// The command contains a command substitution, see
+    /// [`ParseOptions::shell_substitution`](crate::ParseOptions::shell_substitution)
+    ShellSubstitution,
+    /// The mode contains whitespace between the digits, see
+    /// [`ParseOptions::coerce_spaced_mode`](crate::ParseOptions::coerce_spaced_mode)
+    SpacedMode,
+    /// The line is longer than [`ParseOptions::max_line_len`](crate::ParseOptions::max_line_len)
+    LineTooLong {
+        /// Length of the line
+        len: usize,
+        /// Longest line accepted
+        max: usize,
+    },
+}
+
+impl ParseError {
+    pub(crate) fn new(span: Range<usize>, kind: ParseErrorKind) -> Self {
+        Self {
+            line: 0,
+            span,
+            source_line: String::new(),
+            kind,
+        }
+    }
+
+    pub(crate) fn from_pest(err: pest::error::Error<Rule>) -> Self {
+        let span = match err.location {
+            InputLocation::Pos(pos) => pos..pos,
+            InputLocation::Span((start, end)) => start..end,
+        };
+        Self::new(
+            span,
+            ParseErrorKind::Grammar(err.variant.message().into_owned()),
+        )
+    }
+
+    pub(crate) fn at_line(mut self, line: usize, source_line: &str) -> Self {
+        self.line = line;
+        self.source_line = source_line.into();
+        self
+    }
+
+    /// Column of the offending token, starting from 1
+    pub fn column(&self) -> usize {
+        self.source_line
+            .get(..self.span.start)
+            .map_or(self.span.start, |s| s.chars().count())
+            + 1
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ParseErrorKind::Grammar(msg) => write!(f, "{}", msg),
+            ParseErrorKind::Regex(_) => write!(f, "invalid regex"),
+            ParseErrorKind::Number(_) => write!(f, "invalid number"),
+            ParseErrorKind::OctalDigit(c) => write!(f, "invalid octal digit '{}' in mode", c),
+            ParseErrorKind::DeviceNumber { value, max } => write!(
+                f,
+                "device number {} exceeds the kernel limit of {}",
+                value, max
+            ),
+            ParseErrorKind::LineTooLong { len, max } => write!(
+                f,
+                "line is {} bytes long, more than the limit of {}",
+                len, max
+            ),
+            ParseErrorKind::Utf8(_) => write!(f, "invalid UTF-8"),
+            ParseErrorKind::SpacedMode => write!(f, "whitespace inside the mode"),
+            ParseErrorKind::ShellSubstitution => write!(f, "command substitution in the command"),
+            ParseErrorKind::Extension(what) => {
+                write!(f, "{} requires the `extensions` feature", what)
+            }
+        }?;
+        writeln!(f, " at line {}, column {}", self.line, self.column())?;
+
+        // Keep tabs in the padding so that the carets line up with the source
+        let pad: String = self
+            .source_line
+            .get(..self.span.start)
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = self
+            .source_line
+            .get(self.span.clone())
+            .map_or(0, |s| s.chars().count())
+            .max(1);
+        let gutter = " ".repeat(self.line.to_string().len());
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.source_line)?;
+        write!(f, "{} | {}{}", gutter, pad, "^".repeat(width))?;
+
+        match &self.kind {
+            ParseErrorKind::Grammar(_)
+            | ParseErrorKind::Extension(_)
+            | ParseErrorKind::OctalDigit(_)
+            | ParseErrorKind::DeviceNumber { .. }
+            | ParseErrorKind::SpacedMode
+            | ParseErrorKind::ShellSubstitution
+            | ParseErrorKind::LineTooLong { .. } => Ok(()),
+            ParseErrorKind::Regex(err) => write!(f, "\n{}", err),
+            ParseErrorKind::Number(err) => write!(f, "\n{}", err),
+            ParseErrorKind::Utf8(err) => write!(f, "\n{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ParseErrorKind::Grammar(_)
+            | ParseErrorKind::Extension(_)
+            | ParseErrorKind::OctalDigit(_)
+            | ParseErrorKind::DeviceNumber { .. }
+            | ParseErrorKind::SpacedMode
+            | ParseErrorKind::ShellSubstitution
+            | ParseErrorKind::LineTooLong { .. } => None,
+            ParseErrorKind::Regex(err) => Some(err),
+            ParseErrorKind::Number(err) => Some(err),
+            ParseErrorKind::Utf8(err) => Some(err),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// An error found while reading and parsing a configuration
+pub enum LoadError {
+    /// The configuration could not be read
+    Io(io::Error),
+    /// The configuration was read, but contains an invalid line
+    Parse(ParseError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "cannot read the configuration: {}", err),
+            Self::Parse(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Parse(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(v: io::Error) -> Self {
+        Self::Io(v)
+    }
+}
+
+impl From<ParseError> for LoadError {
+    fn from(v: ParseError) -> Self {
+        Self::Parse(v)
+    }
+}
@@ -0,0 +1,90 @@
+//! Escaping of regexes, so that any [`Regex`](regex::Regex) can be written in the configuration
+//! syntax and parsed back.
+//!
+//! Regexes in the configuration cannot contain whitespace, `;` or non-ASCII characters, so these
+//! are written as `\x{...}` escapes, which match the same character. A device regex cannot start
+//! with `$`, `@`, `-` or `#` either, since they have a meaning at the start of a rule, so it is
+//! prefixed with an empty group `(?:)` in that case.
+//!
+//! The only change in meaning is for raw whitespace in verbose mode (`(?x)`), which is ignored by
+//! the regex but becomes significant once escaped.
+
+use std::borrow::Cow;
+
+fn needs_escape(c: char) -> bool {
+    c == ';' || c.is_whitespace() || c.is_control() || !c.is_ascii()
+}
+
+/// Characters that cannot start a device regex
+const LEADING: &[char] = &['$', '@', '-', '#'];
+
+/// Returns `pattern` in a form accepted by the grammar, with `device` telling whether it is
+/// the regex of a device name filter, which comes first in the rule
+pub(crate) fn escape_regex(pattern: &str, device: bool) -> Cow<'_, str> {
+    let leading = device && pattern.starts_with(LEADING);
+    if !leading && !pattern.contains(needs_escape) {
+        return Cow::Borrowed(pattern);
+    }
+
+    let mut escaped = String::with_capacity(pattern.len() + 8);
+    if leading {
+        escaped.push_str("(?:)");
+    }
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            // An escaped character that needs escaping means the character itself
+            '\\' => match chars.next() {
+                Some(next) if needs_escape(next) => next,
+                next => {
+                    escaped.push(c);
+                    escaped.extend(next);
+                    continue;
+                }
+            },
+            c => c,
+        };
+        if needs_escape(c) {
+            escaped.push_str(&format!("\\x{{{:X}}}", u32::from(c)));
+        } else {
+            escaped.push(c);
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn escaping() {
+        let cases = [
+            ("sd[a-z]", false, "sd[a-z]"),
+            ("a b", false, r"a\x{20}b"),
+            ("a;b\tc", false, r"a\x{3B}b\x{9}c"),
+            (r"a\ b\;", false, r"a\x{20}b\x{3B}"),
+            (r"a\\ b", false, r"a\\\x{20}b"),
+            ("[ é]", false, r"[\x{20}\x{E9}]"),
+            ("$a", false, "$a"),
+            ("$a", true, "(?:)$a"),
+            ("@x", true, "(?:)@x"),
+            ("-x y", true, r"(?:)-x\x{20}y"),
+            ("#", true, "(?:)#"),
+            ("x-", true, "x-"),
+        ];
+        for (pattern, device, expected) in cases {
+            let escaped = escape_regex(pattern, device);
+            assert_eq!(escaped, expected, "{}", pattern);
+            // The escaped form must be equivalent
+            let (original, escaped) = (Regex::new(pattern).unwrap(), Regex::new(&escaped).unwrap());
+            for haystack in ["a b", "a;b\tc", "a\\ b", "é", "$a", "-x y", "x-", "#"] {
+                assert_eq!(
+                    original.find(haystack).map(|m| m.range()),
+                    escaped.find(haystack).map(|m| m.range()),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,441 @@
+//! Expansion of environment variables in commands.
+//!
+//! The supported subset of shell parameter expansion is:
+//! - `$VAR` and `${VAR}`, replaced by the value of `VAR`;
+//! - `${VAR:-default}`, replaced by `default` if `VAR` is unset or empty;
+//! - `${VAR:+alt}`, replaced by `alt` if `VAR` is set and not empty, and by nothing otherwise.
+//!
+//! Variable names are made of ASCII letters, digits and `_`, and cannot start with a digit.
+//! Unset variables expand to nothing, while `default` and `alt` are expanded themselves, so
+//! they can refer to other variables. Anything else, like a `$` not followed by a name or an
+//! unterminated `${`, is kept as is. Quotes and escapes are not interpreted by
+//! [`Command::expand_path`] and [`Command::expand_args`], while [`Command::shell_words`] splits
+//! the command line like the shell run by mdev.
+//!
+//! The variables can also be resolved statically, with [`Conf::resolve_vars`]: then only the
+//! variables found in the environment are replaced, and the other references are kept, so
+//! that mdev can still expand them when running the command.
+
+use crate::{Command, Conf};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Length of the variable name at the start of `s`
+pub(crate) fn name_len(s: &str) -> usize {
+    if s.starts_with(|c: char| c.is_ascii_digit()) {
+        return 0;
+    }
+    s.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(s.len())
+}
+
+/// Position of the `}` closing the `${` just before `s`, skipping nested `${...}`
+fn closing_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '$' if chars.next_if(|&(_, c)| c == '{').is_some() => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Expands the content of `${...}`, or returns [`None`] if it is not supported, or if its
+/// variable is unset and `keep_unset` is true
+fn expand_braced(inner: &str, env: &HashMap<String, String>, keep_unset: bool) -> Option<String> {
+    let len = name_len(inner);
+    if len == 0 || (keep_unset && !env.contains_key(&inner[..len])) {
+        return None;
+    }
+    let value = env.get(&inner[..len]).filter(|v| !v.is_empty());
+    let rest = &inner[len..];
+    let (op, word) = rest
+        .char_indices()
+        .nth(2)
+        .map_or((rest, ""), |(i, _)| rest.split_at(i));
+    match op {
+        "" => Some(value.cloned().unwrap_or_default()),
+        ":-" => Some(
+            value
+                .cloned()
+                .unwrap_or_else(|| substitute(word, env, keep_unset)),
+        ),
+        ":+" => Some(
+            value
+                .map(|_| substitute(word, env, keep_unset))
+                .unwrap_or_default(),
+        ),
+        _ => None,
+    }
+}
+
+/// Expands the variables in `s`, see the [module documentation](self)
+pub(crate) fn expand_vars(s: &str, env: &HashMap<String, String>) -> String {
+    substitute(s, env, false)
+}
+
+/// Expands the variables in `s` that are set in `env`, keeping the other references as is
+pub(crate) fn resolve_vars(s: &str, env: &HashMap<String, String>) -> String {
+    substitute(s, env, true)
+}
+
+/// Expands the variable reference at the start of `s`, just after a `$`, returning its value
+/// and the length of the reference, or [`None`] if it is not supported, or if its variable is
+/// unset and `keep_unset` is true
+fn expand_reference(
+    s: &str,
+    env: &HashMap<String, String>,
+    keep_unset: bool,
+) -> Option<(String, usize)> {
+    if let Some(body) = s.strip_prefix('{') {
+        let end = closing_brace(body)?;
+        let expanded = expand_braced(&body[..end], env, keep_unset)?;
+        return Some((expanded, end + 2));
+    }
+    let len = name_len(s);
+    let value = env.get(&s[..len]);
+    if len == 0 || (value.is_none() && keep_unset) {
+        return None;
+    }
+    Some((value.cloned().unwrap_or_default(), len))
+}
+
+fn substitute(s: &str, env: &HashMap<String, String>, keep_unset: bool) -> String {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        match expand_reference(rest, env, keep_unset) {
+            Some((expanded, len)) => {
+                out.push_str(&expanded);
+                rest = &rest[len..];
+            }
+            None => out.push('$'),
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Splits `line` in words like a POSIX shell, expanding the variables of `env`.
+///
+/// Single quotes keep their content as is, double quotes keep it in a single word while still
+/// expanding variables, and `\` escapes the next character, or only `$`, `` ` ``, `"` and `\`
+/// inside double quotes. The value of a variable outside of quotes is split on whitespace, and
+/// disappears if it is empty. Quotes inside the default or alternative value of `${VAR:-...}`
+/// are not interpreted, and other shell syntax, like `;`, `|`, command substitutions or globs,
+/// is kept as literal text.
+fn shell_words(line: &str, env: &HashMap<String, String>) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // Whether a word was started, possibly empty like `""`
+    let mut started = false;
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            c if c.is_whitespace() => {
+                if started {
+                    words.push(std::mem::take(&mut word));
+                    started = false;
+                }
+            }
+            '\\' => {
+                if let Some(c) = rest.chars().next() {
+                    word.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+                started = true;
+            }
+            '\'' => {
+                let end = rest.find('\'').unwrap_or(rest.len());
+                word.push_str(&rest[..end]);
+                rest = rest.get(end + 1..).unwrap_or_default();
+                started = true;
+            }
+            '"' => {
+                started = true;
+                while let Some(c) = rest.chars().next() {
+                    rest = &rest[c.len_utf8()..];
+                    match c {
+                        '"' => break,
+                        '\\' => match rest.chars().next() {
+                            Some(c @ ('$' | '`' | '"' | '\\')) => {
+                                word.push(c);
+                                rest = &rest[1..];
+                            }
+                            _ => word.push('\\'),
+                        },
+                        '$' => match expand_reference(rest, env, false) {
+                            Some((value, len)) => {
+                                word.push_str(&value);
+                                rest = &rest[len..];
+                            }
+                            None => word.push('$'),
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '$' => match expand_reference(rest, env, false) {
+                Some((value, len)) => {
+                    rest = &rest[len..];
+                    // Outside of quotes, the value is split in fields
+                    if value.starts_with(char::is_whitespace) && started {
+                        words.push(std::mem::take(&mut word));
+                        started = false;
+                    }
+                    let mut fields = value.split_whitespace();
+                    if let Some(first) = fields.next() {
+                        word.push_str(first);
+                        started = true;
+                    }
+                    for field in fields {
+                        words.push(std::mem::replace(&mut word, field.into()));
+                    }
+                    if value.ends_with(char::is_whitespace) && started {
+                        words.push(std::mem::take(&mut word));
+                        started = false;
+                    }
+                }
+                None => {
+                    word.push('$');
+                    started = true;
+                }
+            },
+            c => {
+                word.push(c);
+                started = true;
+            }
+        }
+    }
+    if started {
+        words.push(word);
+    }
+    words
+}
+
+/// Quotes `s` for a POSIX shell, leaving it as is if it only contains safe characters
+fn shell_quote(s: &str) -> Cow<'_, str> {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !s.is_empty() && s.chars().all(safe) {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(format!("'{}'", s.replace('\'', r"'\''")))
+    }
+}
+
+impl Command {
+    /// The path of the executable, with the variables of `env` expanded.
+    ///
+    /// `$VAR`, `${VAR}`, `${VAR:-default}` and `${VAR:+alt}` are supported, and unset
+    /// variables expand to nothing.
+    pub fn expand_path(&self, env: &HashMap<String, String>) -> String {
+        expand_vars(&self.path, env)
+    }
+
+    /// The arguments, with the variables of `env` expanded like in [`Command::expand_path`]
+    pub fn expand_args(&self, env: &HashMap<String, String>) -> Vec<String> {
+        self.args.iter().map(|arg| expand_vars(arg, env)).collect()
+    }
+
+    /// The words of the command line as the shell run by mdev splits them, with the variables
+    /// of `env` expanded: the path of the executable followed by its arguments.
+    ///
+    /// mdev passes the command line to `sh -c`, so quotes group words and are removed, like in
+    /// `@modprobe -b "$MODALIAS"`, and the value of a variable outside of quotes is split on
+    /// whitespace. Other shell syntax, like `;`, `|`, command substitutions or globs, is kept as
+    /// literal text. The arguments are joined by a single space first, since the runs of
+    /// whitespace between them are not kept by the parser.
+    pub fn shell_words(&self, env: &HashMap<String, String>) -> Vec<String> {
+        let line = std::iter::once(&self.path)
+            .chain(&self.args)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ");
+        shell_words(&line, env)
+    }
+
+    /// The command line run by [`Command::to_process_command`], with each of its
+    /// [words](Command::shell_words) quoted for a POSIX shell, so that it can be shown to the
+    /// user or pasted in a shell
+    pub fn preview(&self, env: &HashMap<String, String>) -> String {
+        let words = self.shell_words(env);
+        let quoted: Vec<_> = words.iter().map(|word| shell_quote(word)).collect();
+        quoted.join(" ")
+    }
+
+    /// Builds a [`std::process::Command`] running the [words](Command::shell_words) of the
+    /// command line, the first one being the executable, with the variables of `env` added to
+    /// its environment.
+    ///
+    /// The command is run directly rather than through `sh -c` like mdev does, so the values of
+    /// the variables cannot inject shell syntax, but the arguments are the same. Nothing is
+    /// spawned, so the caller can still configure the command before running it.
+    pub fn to_process_command(&self, env: &HashMap<String, String>) -> std::process::Command {
+        let words = self.shell_words(env);
+        let (program, args) = words.split_first().map_or(("", &[][..]), |(p, a)| (p, a));
+        let mut command = std::process::Command::new(program);
+        command.args(args).envs(env);
+        command
+    }
+}
+
+impl Conf {
+    /// A copy of the rule with the variables of `env` resolved in the command.
+    ///
+    /// Unlike [`Command::expand_path`], the variables missing from `env` are kept as they are,
+    /// since mdev sets some of them, like `$MDEV`, only when running the command. The other
+    /// fields cannot refer to variables and are copied unchanged.
+    pub fn resolve_vars(&self, env: &HashMap<String, String>) -> Self {
+        let command = self.command.as_ref().map(|command| Command {
+            when: command.when.clone(),
+            path: resolve_vars(&command.path, env),
+            args: command
+                .args
+                .iter()
+                .map(|arg| resolve_vars(arg, env))
+                .collect(),
+        });
+        Self {
+            command,
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn expansion() {
+        let env = env(&[("MDEV", "sda1"), ("ACTION", "add"), ("EMPTY", "")]);
+        let cases = [
+            ("$MDEV", "sda1"),
+            ("/dev/${MDEV}p", "/dev/sda1p"),
+            ("$MDEV.$ACTION", "sda1.add"),
+            ("$UNSET-$MDEV", "-sda1"),
+            ("${UNSET}", ""),
+            ("${EMPTY:-none}", "none"),
+            ("${MDEV:+--device=$MDEV}", "--device=sda1"),
+            ("${UNSET:+alt}", ""),
+            ("${UNSET:-${ACTION:-x}}", "add"),
+            ("${UNSET:-{}}", "{}"),
+            ("100$", "100$"),
+            ("$1 $$", "$1 $$"),
+            ("${MDEV", "${MDEV"),
+            ("${MDEV:=x}", "${MDEV:=x}"),
+            ("${}", "${}"),
+            ("${MDEV€}", "${MDEV€}"),
+            ("${UNSET:-}", ""),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(expand_vars(input, &env), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn resolution() {
+        let env = env(&[("HELPERS", "/lib/mdev"), ("EMPTY", "")]);
+        let cases = [
+            ("$HELPERS/notify", "/lib/mdev/notify"),
+            ("${HELPERS}/$MDEV", "/lib/mdev/$MDEV"),
+            ("${MDEV:-none}", "${MDEV:-none}"),
+            ("${EMPTY:-$MDEV}", "$MDEV"),
+            ("${HELPERS:+$ACTION}", "$ACTION"),
+            ("$EMPTY.$UNSET", ".$UNSET"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(resolve_vars(input, &env), expected, "{}", input);
+        }
+
+        let input = "sd[a-z] root:disk 660 =disk/$MDEV @$HELPERS/mount $MDEV ${OPTS:-ro}";
+        let confs = crate::parse_resolved(input, &env);
+        let command = confs[0].command.as_ref().unwrap();
+        assert_eq!(command.path, "/lib/mdev/mount");
+        assert_eq!(command.args, ["$MDEV", "${OPTS:-ro}"]);
+        assert_eq!(confs[0].on_creation, crate::parse(input)[0].on_creation);
+        assert_eq!(confs[1], Conf::default());
+    }
+
+    #[test]
+    fn preview() {
+        let conf = &crate::parse(
+            "$MODALIAS=.* root:root 660 @/sbin/modprobe -b \"$MODALIAS\" \"$LABEL\" $LABEL ${UNSET} \"it's\"",
+        )[0];
+        let command = conf.command.as_ref().unwrap();
+        let env = env(&[("MODALIAS", "usb:v1D6B"), ("LABEL", "My Disk; rm -rf /")]);
+        // The shell removes the quotes, and splits the value of `$LABEL` outside of them
+        assert_eq!(
+            command.preview(&env),
+            r#"/sbin/modprobe -b usb:v1D6B 'My Disk; rm -rf /' My 'Disk;' rm -rf / 'it'\''s'"#
+        );
+    }
+
+    #[test]
+    fn words() {
+        let env = env(&[("MDEV", "sda1"), ("OPTS", " -o  ro "), ("EMPTY", "")]);
+        let cases: &[(&str, &[&str])] = &[
+            ("a b\tc", &["a", "b", "c"]),
+            ("\"$MDEV\" '$MDEV' \\$MDEV", &["sda1", "$MDEV", "$MDEV"]),
+            ("\"a  b\"c 'd \" e'", &["a  bc", "d \" e"]),
+            ("\"\" '' $EMPTY \"$EMPTY\"", &["", "", ""]),
+            ("mount$OPTS/dev/$MDEV", &["mount", "-o", "ro", "/dev/sda1"]),
+            ("x${OPTS}y", &["x", "-o", "ro", "y"]),
+            ("\"\\$\\\"\\a\" a\\ b", &["$\"\\a", "a b"]),
+            ("${EMPTY:-none} $ 100$", &["none", "$", "100$"]),
+            ("'unterminated", &["unterminated"]),
+        ];
+        for &(line, expected) in cases {
+            assert_eq!(shell_words(line, &env), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn command() {
+        let conf = &crate::parse(
+            "$MODALIAS=.* root:root 660 @${HELPERS:-/sbin}/modprobe -b ${MODALIAS:-none}",
+        )[0];
+        let command = conf.command.as_ref().unwrap();
+        let present = env(&[("MODALIAS", "usb:v1D6B"), ("HELPERS", "/lib/mdev")]);
+        assert_eq!(command.expand_path(&present), "/lib/mdev/modprobe");
+        assert_eq!(command.expand_args(&present), ["-b", "usb:v1D6B"]);
+        let absent = env(&[]);
+        assert_eq!(command.expand_path(&absent), "/sbin/modprobe");
+        assert_eq!(command.expand_args(&absent), ["-b", "none"]);
+
+        let quoted = &crate::parse("$MODALIAS=.* root:root 660 @modprobe -b \"$MODALIAS\"")[0];
+        let process = quoted
+            .command
+            .as_ref()
+            .unwrap()
+            .to_process_command(&present);
+        assert_eq!(process.get_program(), "modprobe");
+        assert_eq!(process.get_args().collect::<Vec<_>>(), ["-b", "usb:v1D6B"]);
+
+        let process = command.to_process_command(&present);
+        assert_eq!(process.get_program(), "/lib/mdev/modprobe");
+        assert_eq!(process.get_args().collect::<Vec<_>>(), ["-b", "usb:v1D6B"]);
+        let mut envs: Vec<_> = process.get_envs().collect();
+        envs.sort();
+        assert_eq!(
+            envs,
+            [
+                ("HELPERS".as_ref(), Some("/lib/mdev".as_ref())),
+                ("MODALIAS".as_ref(), Some("usb:v1D6B".as_ref()))
+            ]
+        );
+    }
+}
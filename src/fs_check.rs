@@ -0,0 +1,82 @@
+use crate::Conf;
+use std::path::Path;
+
+impl Conf {
+    /// Whether the command of the rule is installed, checked on the system running this code.
+    ///
+    /// Returns [`None`] if the rule has no command or if its path is relative, since mdev looks
+    /// it up in `$PATH`. Otherwise the path must lead to a file, and on Unix one that can be
+    /// executed by someone.
+    pub fn command_exists(&self) -> Option<bool> {
+        let path = Path::new(&self.command.as_ref()?.path);
+        if !path.is_absolute() {
+            return None;
+        }
+        Some(path.metadata().is_ok_and(|metadata| {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+            }
+            #[cfg(not(unix))]
+            {
+                metadata.is_file()
+            }
+        }))
+    }
+}
+
+/// Returns the indices of the rules running a command that is not installed, see
+/// [`Conf::command_exists`].
+pub fn lint_missing_commands(confs: &[Conf]) -> Vec<usize> {
+    confs
+        .iter()
+        .enumerate()
+        .filter(|(_, conf)| conf.command_exists() == Some(false))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn missing_commands() {
+        let dir = std::env::temp_dir().join(format!("mdev-parser-fs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let helper = dir.join("helper");
+        std::fs::write(&helper, "#!/bin/sh\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let data = dir.join("data");
+            std::fs::write(&data, "").unwrap();
+            std::fs::set_permissions(&helper, std::fs::Permissions::from_mode(0o755)).unwrap();
+            std::fs::set_permissions(&data, std::fs::Permissions::from_mode(0o644)).unwrap();
+        }
+
+        let input = format!(
+            "\
+sda root:disk 660 @{helper}
+sdb root:disk 660 @{missing}
+sdc root:disk 660 @{data}
+sdd root:disk 660 @modprobe sd_mod
+sde root:disk 660 @{dir}
+",
+            helper = helper.display(),
+            missing = dir.join("missing").display(),
+            data = dir.join("data").display(),
+            dir = dir.display(),
+        );
+        let confs = parse(&input);
+        assert_eq!(confs[0].command_exists(), Some(true));
+        assert_eq!(confs[1].command_exists(), Some(false));
+        assert_eq!(confs[3].command_exists(), None);
+        assert_eq!(confs[5].command_exists(), None);
+        #[cfg(unix)]
+        assert_eq!(lint_missing_commands(&confs), [1, 2, 4]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+//! [`Arbitrary`] implementations that only generate rules accepted by the grammar, so that
+//! formatting a generated [`Conf`] and parsing it again gives back the same value.
+
+use crate::{Command, Conf, DeviceRegex, EnvMatch, Filter, MajMin, OnCreation, WhenToRun};
+use arbitrary::{Arbitrary, Result, Unstructured};
+use regex::Regex;
+
+const LOWER: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+const UPPER: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const LITERAL: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789_:/";
+const PATH: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789_.-%";
+
+fn string_from(u: &mut Unstructured<'_>, alphabet: &[u8], len: usize) -> Result<String> {
+    (0..len)
+        .map(|_| u.choose(alphabet).map(|&c| char::from(c)))
+        .collect()
+}
+
+/// A regex, which can contain characters that need to be escaped in the configuration
+fn regex(u: &mut Unstructured<'_>) -> Result<Regex> {
+    let mut pattern = String::from(*u.choose(&["", "", "", "", "$", "@", "-", "#"])?);
+    for _ in 0..u.int_in_range(1..=4)? {
+        match u.int_in_range(0..=6)? {
+            0 => pattern.push_str(".*"),
+            1 => pattern.push_str("[0-9]"),
+            2 => pattern.push_str("[a-z]"),
+            3 => pattern.push_str(r"\."),
+            4 => {
+                let len = u.int_in_range(1..=6)?;
+                pattern.push('(');
+                pattern.push_str(&string_from(u, LITERAL, len)?);
+                pattern.push(')');
+            }
+            5 => pattern.push_str(u.choose(&[" ", "\t", ";", "é", r"\ ", r"\;", "[ ;]"])?),
+            _ => pattern.push_str(&string_from(u, LITERAL, 1)?),
+        }
+        pattern.push_str(u.choose(&["", "", "*", "+", "?"])?);
+    }
+    Ok(Regex::new(&pattern).expect("generated regexes are valid"))
+}
+
+fn envvar(u: &mut Unstructured<'_>) -> Result<String> {
+    let len = u.int_in_range(0..=8)?;
+    let mut envvar = string_from(u, UPPER, 1)?;
+    envvar.push_str(&string_from(u, b"ABCDEFGHIJKLMNOPQRSTUVWXYZ_", len)?);
+    Ok(envvar)
+}
+
+fn name(u: &mut Unstructured<'_>) -> Result<String> {
+    let len = u.int_in_range(1..=8)?;
+    string_from(u, LOWER, len)
+}
+
+/// A label, which can contain whitespace but not at its ends, with the `extensions` feature
+fn label(u: &mut Unstructured<'_>) -> Result<Option<String>> {
+    if !cfg!(feature = "extensions") || u.ratio(1, 2)? {
+        return Ok(None);
+    }
+    let mut label = Vec::new();
+    for _ in 0..u.int_in_range(1..=3)? {
+        let len = u.int_in_range(1..=8)?;
+        label.push(string_from(u, PATH, len)?);
+    }
+    Ok(Some(label.join(u.choose(&["-", " ", ":"])?)))
+}
+
+/// A relative path, optionally ending with `/`
+fn path(u: &mut Unstructured<'_>) -> Result<String> {
+    let mut path = Vec::new();
+    for _ in 0..u.int_in_range(1..=3)? {
+        let len = u.int_in_range(1..=8)?;
+        path.push(string_from(u, PATH, len)?);
+    }
+    let mut path = path.join("/");
+    if u.ratio(1, 4)? {
+        path.push('/');
+    }
+    Ok(path)
+}
+
+impl<'a> Arbitrary<'a> for EnvMatch {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            envvar: envvar(u)?,
+            regex: regex(u)?,
+            negated: cfg!(feature = "extensions") && u.ratio(1, 4)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for DeviceRegex {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let envvar = if u.ratio(1, 4)? {
+            Some(envvar(u)?)
+        } else {
+            None
+        };
+        Ok(Self::new(envvar, regex(u)?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for MajMin {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let maj = u.int_in_range(0..=MajMin::MAX_MAJOR)?;
+        let min = u.int_in_range(0..=MajMin::MAX_MINOR)?;
+        let min2 = if u.arbitrary()? {
+            Some(u.int_in_range(min..=MajMin::MAX_MINOR)?)
+        } else {
+            None
+        };
+        Ok(Self { maj, min, min2 })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Filter {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(if u.arbitrary()? {
+            Self::DeviceRegex(u.arbitrary()?)
+        } else if cfg!(feature = "extensions") && u.ratio(1, 4)? {
+            let list = (0..u.int_in_range(2..=3)?)
+                .map(|_| u.arbitrary())
+                .collect::<Result<_>>()?;
+            Self::MajMinList(list)
+        } else {
+            Self::MajMin(u.arbitrary()?)
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for OnCreation {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=2)? {
+            0 => Self::Move(path(u)?),
+            1 => Self::SymLink(path(u)?),
+            _ => Self::Prevent,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for WhenToRun {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=2)? {
+            0 => Self::After,
+            1 => Self::Before,
+            _ => Self::Both,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Command {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let when = u.arbitrary()?;
+        let mut path = path(u)?;
+        if u.arbitrary()? {
+            path.insert(0, '/');
+        }
+        let mut args = (0..u.int_in_range(0..=3)?)
+            .map(|_| {
+                let len = u.int_in_range(1..=8)?;
+                // Any printable ASCII character except the space
+                (0..len)
+                    .map(|_| u.int_in_range(b'!'..=b'~').map(char::from))
+                    .collect()
+            })
+            .collect::<Result<Vec<String>>>()?;
+        // With the `extensions` feature, a `\` at the end of the line continues it
+        if cfg!(feature = "extensions") {
+            if let Some(last) = args.last_mut().filter(|arg| arg.ends_with('\\')) {
+                last.push('_');
+            }
+        }
+        Ok(Self { when, path, args })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Conf {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let envmatches = (0..u.int_in_range(0..=3)?)
+            .map(|_| u.arbitrary())
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            stop: u.arbitrary()?,
+            envmatches,
+            filter: u.arbitrary()?,
+            user: name(u)?,
+            group: name(u)?,
+            mode: u.int_in_range(0..=0o7777)?,
+            on_creation: u.arbitrary()?,
+            command: u.arbitrary()?,
+            label: label(u)?,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Calls `f` with many arbitrary values, generated deterministically
+    pub(crate) fn for_arbitrary<T: for<'a> Arbitrary<'a>>(mut f: impl FnMut(T)) {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut data = vec![0; 512];
+        for _ in 0..1000 {
+            for byte in &mut data {
+                // xorshift64
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                *byte = state as u8;
+            }
+            f(T::arbitrary(&mut Unstructured::new(&data)).unwrap());
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for_arbitrary(|conf: Conf| {
+            let line = conf.to_string();
+            let parsed = crate::parse_strict(&line).unwrap_or_else(|err| panic!("{}", err));
+            assert_eq!(parsed, [conf, Conf::default()], "{}", line);
+        });
+    }
+}
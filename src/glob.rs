@@ -0,0 +1,113 @@
+//! Translation of shell globs to regexes, for filters written as `glob:pattern`.
+//!
+//! The supported syntax is the one of the shell: `*` matches any sequence of characters and `?`
+//! a single character, both except `/`, while `[...]` matches one of the characters listed,
+//! `[!...]` or `[^...]` any other. Anything else, including a `[` that is never closed, matches
+//! itself.
+
+use crate::{DeviceRegex, Rule};
+use pest::iterators::Pair;
+use regex::Regex;
+
+/// Prefix of a filter written as a glob
+pub(crate) const GLOB_PREFIX: &str = "glob:";
+
+/// Returns a regex matching the whole strings that `glob` matches
+pub(crate) fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::with_capacity(glob.len() + 8);
+    regex.push('^');
+    let mut rest = glob;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => match class(rest) {
+                Some((class, len)) => {
+                    regex.push_str(&class);
+                    rest = &rest[len..];
+                }
+                None => regex.push_str(r"\["),
+            },
+            c => regex.push_str(&regex_syntax::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// Translates the bracket expression starting right after `[`, returning it together with the
+/// length it takes in the glob, or `None` if it is not closed
+fn class(glob: &str) -> Option<(String, usize)> {
+    let (negated, body) = match glob.strip_prefix(['!', '^']) {
+        Some(body) => (true, body),
+        None => (false, glob),
+    };
+    // A `]` right after the opening bracket is part of the set
+    let skip = usize::from(body.starts_with(']'));
+    let end = skip + body[skip..].find(']')?;
+
+    let mut class = String::from(if negated { "[^" } else { "[" });
+    for c in body[..end].chars() {
+        // Escape whatever has a special meaning inside a regex class but not in a glob
+        if matches!(c, '\\' | '[' | ']' | '^' | '&' | '~') {
+            class.push('\\');
+        }
+        class.push(c);
+    }
+    class.push(']');
+    Some((class, glob.len() - body.len() + end + 1))
+}
+
+impl DeviceRegex {
+    /// Creates a filter matching the shell glob `glob` against the device name, or against the
+    /// value of `envvar` if present.
+    ///
+    /// The filter is written back as `glob:pattern`, which requires the `extensions` feature
+    /// to be parsed.
+    pub fn from_glob(envvar: Option<String>, glob: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            glob: Some(glob.into()),
+            ..Self::new(envvar, Regex::new(&glob_to_regex(glob))?)
+        })
+    }
+}
+
+/// Returns the glob of a regex token written as `glob:pattern`, if globs are enabled
+pub(crate) fn glob_from_rule<'i>(v: &Pair<'i, Rule>) -> Option<&'i str> {
+    debug_assert_eq!(v.as_rule(), Rule::regex);
+    if cfg!(feature = "extensions") {
+        v.as_str().strip_prefix(GLOB_PREFIX)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(glob: &str, name: &str) -> bool {
+        Regex::new(&glob_to_regex(glob)).unwrap().is_match(name)
+    }
+
+    #[test]
+    fn translation() {
+        assert_eq!(glob_to_regex("sd*"), "^sd[^/]*$");
+        assert_eq!(glob_to_regex("tty?"), "^tty[^/]$");
+        assert_eq!(glob_to_regex("sd[a-z]"), "^sd[a-z]$");
+        assert_eq!(glob_to_regex("sd[!a]"), "^sd[^a]$");
+        assert_eq!(glob_to_regex("a.b+"), r"^a\.b\+$");
+        assert_eq!(glob_to_regex("a[b"), r"^a\[b$");
+
+        assert!(matches("sd[a-z]", "sda"));
+        assert!(!matches("sd[a-z]", "sdaa"));
+        assert!(matches("sd*", "sd"));
+        assert!(!matches("sd*", "sd/a"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[!]a]", "b"));
+        assert!(!matches("[!]a]", "]"));
+        assert!(matches("[[]", "["));
+        assert!(matches("a[b", "a[b"));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::options::Context;
+use crate::{parse_line, Conf, ConfParser, ParseError, ParseOptions, Rule};
+use pest::{iterators::Pair, Parser, RuleType};
+use tracing::warn;
+
+/// The [pest] grammar of the configuration, which can be included in other grammars.
+///
+/// The `rule` production matches a single rule, without the trailing newline.
+pub const GRAMMAR: &str = include_str!("../assets/conf_grammar.pest");
+
+/// The parts of a rule, in the order they are written, see [`grammar_rules`]
+const RULES: &[(&str, &str)] = &[
+    (
+        "stop",
+        "optional `-` prefix: the search for matching rules goes on after this one",
+    ),
+    (
+        "env_match",
+        "zero or more `VAR=regex;` conditions on the environment, matching anywhere in the \
+         value; `VAR!=regex;` negates one and requires the `extensions` feature",
+    ),
+    (
+        "filter",
+        "a regex matched against the whole device name, `$VAR=regex` matched against the \
+         whole value of a variable, or `@maj,min` and `@maj,min-min2` device numbers; \
+         `glob:` patterns, hexadecimal numbers and lists of ranges require the `extensions` \
+         feature",
+    ),
+    (
+        "usergroup",
+        "`user:group` owning the node, made of ASCII letters",
+    ),
+    (
+        "mode",
+        "three octal digits giving the permissions of the node, like `660`; omitting it or \
+         writing `user:group:mode` requires the `extensions` feature",
+    ),
+    (
+        "on_creation",
+        "optional `=path` moving the node, `>path` moving it and linking it back, or `!` \
+         preventing its creation; `%0`-`%9` are replaced by the regex captures",
+    ),
+    (
+        "command",
+        "optional `@` (after creation), `$` (before removal) or `*` (both) followed by the \
+         path of the executable and its arguments, run with `sh -c`",
+    ),
+];
+
+/// The names and descriptions of the parts of a rule, in the order they are written, for
+/// generating help text.
+///
+/// The names are those of the productions of [`GRAMMAR`], except `filter`, which stands for
+/// the alternatives accepted after the envmatches. The list only grows, so tools can rely on
+/// the existing names.
+pub fn grammar_rules() -> &'static [(&'static str, &'static str)] {
+    RULES
+}
+
+/// A configuration exercising every production of the grammar, written in the canonical form
+/// produced by [`Display`](std::fmt::Display)
+const REPRESENTATIVE: &[(&str, bool)] = &[
+    ("# comment", false),
+    ("", false),
+    ("  \t", false),
+    ("sd[a-z] root:disk 660", true),
+    ("-SUBSYSTEM=net;DEVPATH=.*/net/.*;.* root:root 600", true),
+    (
+        "$MODALIAS=.* root:root 660 @modprobe -b \"$MODALIAS\"",
+        true,
+    ),
+    ("@42,17-125 root:root 660 =disk/", true),
+    ("@42,17 root:root 640 >block/%0", true),
+    ("null root:root 666 !", true),
+    (
+        "loop([0-9]+) root:disk 660 >loop/%1 $/bin/umount-loop %1",
+        true,
+    ),
+    (
+        "SUBSYSTEM=usb;.* root:root 660 */opt/mdev/helpers/dev-bus-usb",
+        true,
+    ),
+];
+
+/// Checks that the grammar and the parser agree, by parsing a configuration that covers every
+/// production of the grammar.
+///
+/// Every rule must be parsed successfully and formatted back to the same line, while comments
+/// and empty lines must not produce any rule. This is meant for downstream crates that want to
+/// make sure the parser behaves as expected after an upgrade.
+pub fn validate_grammar() -> Result<(), String> {
+    let options = ParseOptions::default();
+    for (i, &(line, is_rule)) in REPRESENTATIVE.iter().enumerate() {
+        let conf = parse_line(i + 1, line, &options).map_err(|err| err.to_string())?;
+        match conf {
+            Some(conf) if is_rule => {
+                let formatted = conf.to_string();
+                if formatted != line {
+                    return Err(format!("`{}` was formatted as `{}`", line, formatted));
+                }
+            }
+            Some(_) => return Err(format!("`{}` was parsed as a rule", line)),
+            None if is_rule => return Err(format!("`{}` was not parsed as a rule", line)),
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Parses a rule matched by another [pest] grammar, for example one embedding [`GRAMMAR`].
+///
+/// The text of `pair` must be a single rule, which is parsed again with [`ConfParser`]. The
+/// line and the span of the returned [`ParseError`] refer to the input `pair` comes from.
+pub fn parse_rule_pair<R: RuleType>(pair: &Pair<'_, R>) -> Result<Conf, ParseError> {
+    let start = pair.as_span().start_pos();
+    let (line, column) = start.line_col();
+    let source_line = start.line_of().trim_end_matches(['\r', '\n']);
+    let offset = source_line
+        .char_indices()
+        .nth(column - 1)
+        .map_or(source_line.len(), |(i, _)| i);
+
+    let options = ParseOptions::default();
+    let mut ctx = Context::new(&options);
+    let conf = ConfParser::parse(Rule::single_rule, pair.as_str())
+        .map_err(ParseError::from_pest)
+        .and_then(|mut v| {
+            let rule = v.next().unwrap().into_inner().next().unwrap();
+            Conf::from_rule(rule, &mut ctx)
+        });
+    let locate = |mut err: ParseError| {
+        err.span = err.span.start + offset..err.span.end + offset;
+        err.at_line(line, source_line)
+    };
+    for warning in ctx.warnings {
+        warn!("{}", locate(warning));
+    }
+    conf.map_err(locate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_are_productions() {
+        let names: Vec<_> = grammar_rules().iter().map(|(name, _)| *name).collect();
+        assert_eq!(
+            names,
+            [
+                "stop",
+                "env_match",
+                "filter",
+                "usergroup",
+                "mode",
+                "on_creation",
+                "command"
+            ]
+        );
+        for name in names.iter().filter(|&&name| name != "filter") {
+            let production = format!("\n{} = ", name);
+            assert!(GRAMMAR.contains(&production), "{}", name);
+        }
+    }
+
+    #[test]
+    fn grammar_is_valid() {
+        validate_grammar().unwrap();
+    }
+
+    #[test]
+    fn embedded() {
+        // A grammar with a section of rules, similar to what a downstream crate would write
+        mod section_rules {
+            #[derive(pest_derive::Parser)]
+            #[grammar_inline = r#"
+                section = { SOI ~ "[devices]" ~ NEWLINE ~ (rule ~ NEWLINE)* ~ EOI }
+                rule = @{ (!NEWLINE ~ ANY)+ }
+            "#]
+            pub struct SectionParser;
+        }
+        use section_rules::SectionParser;
+
+        let input = "[devices]\nsda root:disk 660\n  null root:root 6x6\n";
+        let section = SectionParser::parse(section_rules::Rule::section, input)
+            .unwrap()
+            .next()
+            .unwrap();
+        let mut rules = section
+            .into_inner()
+            .filter(|pair| pair.as_rule() == section_rules::Rule::rule);
+
+        let conf = parse_rule_pair(&rules.next().unwrap()).unwrap();
+        assert_eq!(conf.to_string(), "sda root:disk 660");
+        let err = parse_rule_pair(&rules.next().unwrap()).unwrap_err();
+        assert_eq!(err.line, 3);
+        assert_eq!(err.source_line, "  null root:root 6x6");
+        assert_eq!(err.column(), 18);
+        assert!(GRAMMAR.contains("rule = {"));
+    }
+}
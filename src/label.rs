@@ -0,0 +1,92 @@
+//! Names given to rules with a directive comment on the line before them, like
+//! `#name: usb-helpers`, with the `extensions` feature.
+//!
+//! mdev reads the directive as a plain comment, so it does not change how the configuration is
+//! applied. The name is only attached to a rule on the very next line: a blank line, another
+//! comment or an invalid line in between discards it.
+
+use crate::ParseEvent;
+
+/// Start of the comment naming the next rule
+pub(crate) const LABEL_DIRECTIVE: &str = "#name:";
+
+/// The name given by `comment`, if it is a non-empty directive and labels are enabled
+pub(crate) fn label_of(comment: &str) -> Option<&str> {
+    if !cfg!(feature = "extensions") {
+        return None;
+    }
+    comment
+        .strip_prefix(LABEL_DIRECTIVE)
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+}
+
+#[derive(Clone, Debug, Default)]
+/// The label waiting for the rule on the next line
+pub(crate) struct Labels {
+    pending: Option<String>,
+}
+
+impl Labels {
+    /// Attaches the pending label to the rule of `event`, or updates it with the next line
+    pub(crate) fn apply(&mut self, event: &mut ParseEvent) {
+        match event {
+            ParseEvent::Rule(conf) => conf.label = self.take(),
+            ParseEvent::Comment(comment) => self.skip(Some(comment)),
+            ParseEvent::Blank | ParseEvent::Error(_) => self.skip(None),
+            ParseEvent::Continued => {}
+        }
+    }
+
+    /// Returns the label of the rule on the current line
+    pub(crate) fn take(&mut self) -> Option<String> {
+        self.pending.take()
+    }
+
+    /// Updates the pending label with a line without a rule: a comment, or [`None`] for a blank
+    /// or invalid line
+    pub(crate) fn skip(&mut self, comment: Option<&str>) {
+        self.pending = comment.and_then(label_of).map(Into::into);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_strict;
+
+    #[test]
+    fn labels() {
+        let input = "\
+#name: usb-helpers
+SUBSYSTEM=usb;.* root:root 660 */lib/mdev/usb
+#name:
+sda root:disk 660
+#name: lost
+
+sdb root:disk 660
+#name: lost
+# comment
+sdc root:disk 660
+  #name:   disks  
+sd[d-z] root:disk 660
+";
+        let confs = parse_strict(input).unwrap();
+        let labels: Vec<_> = confs.iter().map(|conf| conf.label.as_deref()).collect();
+        #[cfg(feature = "extensions")]
+        {
+            assert_eq!(
+                labels,
+                [Some("usb-helpers"), None, None, None, Some("disks"), None]
+            );
+            assert_eq!(
+                confs[0].to_string(),
+                "#name: usb-helpers\nSUBSYSTEM=usb;.* root:root 660 */lib/mdev/usb"
+            );
+            assert_eq!(parse_strict(&confs[4].to_string()).unwrap()[0], confs[4]);
+        }
+        #[cfg(not(feature = "extensions"))]
+        {
+            assert_eq!(labels, [None; 6]);
+        }
+    }
+}
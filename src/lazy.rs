@@ -0,0 +1,164 @@
+use crate::{
+    continuation::Continuation, label::Labels, parse_line, stream::BOM, Conf, ConfParser,
+    ParseError, ParseOptions, Rule,
+};
+use pest::{iterators::Pair, Parser};
+use std::sync::OnceLock;
+
+/// A rule whose regexes are compiled the first time it is accessed.
+///
+/// The line is checked against the grammar when it is parsed, but building the [`Conf`] (and
+/// therefore compiling its regexes) is deferred until [`LazyConf::get`] is called.
+#[derive(Debug)]
+pub struct LazyConf {
+    line_number: usize,
+    source: String,
+    label: Option<String>,
+    conf: OnceLock<Result<Conf, ParseError>>,
+}
+
+impl LazyConf {
+    fn new(line_number: usize, source: String, label: Option<String>) -> Self {
+        Self {
+            line_number,
+            source,
+            label,
+            conf: OnceLock::new(),
+        }
+    }
+
+    /// The line of the configuration containing the rule, or the lines of a rule continued with
+    /// `\\` joined together
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the compiled rule, compiling it on the first call
+    pub fn get(&self) -> Result<&Conf, &ParseError> {
+        self.conf
+            .get_or_init(|| {
+                parse_line(self.line_number, &self.source, &ParseOptions::default()).map(|conf| {
+                    Conf {
+                        label: self.label.clone(),
+                        ..conf.expect("line was checked to contain a rule")
+                    }
+                })
+            })
+            .as_ref()
+    }
+}
+
+impl From<Conf> for LazyConf {
+    fn from(conf: Conf) -> Self {
+        Self {
+            line_number: 0,
+            source: conf.to_string(),
+            label: conf.label.clone(),
+            conf: OnceLock::from(Ok(conf)),
+        }
+    }
+}
+
+/// The content of the line according to the grammar, or [`None`] if it is invalid
+fn content(line: &str) -> Option<Pair<'_, Rule>> {
+    let mut v = ConfParser::parse(Rule::line, line)
+        .map_err(|err| tracing::error!("parsing error: {}", err))
+        .ok()?;
+    Some(v.next().unwrap().into_inner().next().unwrap())
+}
+
+/// Same as [`parse`](crate::parse), but the regexes are compiled lazily.
+///
+/// Lines that do not respect the grammar are still excluded, while invalid regexes are only
+/// reported by [`LazyConf::get`].
+pub fn parse_lazy(input: &str) -> Vec<LazyConf> {
+    let mut confs = Vec::new();
+    let mut continuation = Continuation::default();
+    let mut labels = Labels::default();
+    let mut lines = input.strip_prefix(BOM).unwrap_or(input).lines();
+    let mut line_number = 0;
+    loop {
+        let (first, line) = match lines.next() {
+            Some(line) => {
+                line_number += 1;
+                match continuation.push(line_number, line, '#') {
+                    Some(joined) => joined,
+                    // The rule is added with its last line
+                    None => continue,
+                }
+            }
+            None => match continuation.finish() {
+                Some((first, line)) => (first, line.into()),
+                None => break,
+            },
+        };
+        match content(&line) {
+            Some(content) if content.as_rule() == Rule::rule => {
+                confs.push(LazyConf::new(first, line.into_owned(), labels.take()));
+            }
+            Some(content) if content.as_rule() == Rule::comment => {
+                labels.skip(Some(content.as_str()));
+            }
+            _ => labels.skip(None),
+        }
+    }
+    confs.push(Conf::default().into());
+    confs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that the lazy rules of `input` are the same as the eager ones
+    fn assert_lazy_matches_eager(input: &str) -> (Vec<Conf>, Vec<LazyConf>) {
+        let eager = crate::parse(input);
+        let lazy = parse_lazy(input);
+        assert_eq!(eager.len(), lazy.len());
+        for (a, b) in eager.iter().zip(&lazy) {
+            assert_eq!(a, b.get().unwrap());
+        }
+        (eager, lazy)
+    }
+
+    #[test]
+    fn lazy_matches_eager() {
+        let input = "sd[a-z] root:disk 660\n# comment\n\n$MODALIAS=.* root:root 660 @modprobe -b \"$MODALIAS\"\nsdb root:root 660 @/bin/a \\\n  b\n#name: disks\nsda root:root 660\n";
+        let (eager, lazy) = assert_lazy_matches_eager(input);
+        // Without the `extensions` feature the `\` is an argument, and `  b` is invalid
+        let extensions = cfg!(feature = "extensions");
+        let (source, arg) = match extensions {
+            true => ("sdb root:root 660 @/bin/a   b", "b"),
+            false => ("sdb root:root 660 @/bin/a \\", "\\"),
+        };
+        assert_eq!(lazy[2].source(), source);
+        assert_eq!(eager[2].command.as_ref().unwrap().args, [arg]);
+        let label = lazy[3].get().unwrap().label.as_deref();
+        assert_eq!(label, extensions.then_some("disks"));
+    }
+
+    #[test]
+    fn lazy_matches_stream_parser() {
+        // Every feature of the line layer: byte order mark, labels and continued lines, also
+        // at the end of the input
+        let input = "\u{feff}#name: disks\nsd[a-z] root:disk 660 \\\n  @/bin/mount \\\n\t-r\n#name: lost\nsda root\nnull root:root 666\n#name: tail\n$MODALIAS=.* root:root \\\n660 \\";
+        let (eager, _) = assert_lazy_matches_eager(input);
+        let labels: Vec<_> = eager.iter().map(|conf| conf.label.as_deref()).collect();
+        if cfg!(feature = "extensions") {
+            assert_eq!(labels, [Some("disks"), None, Some("tail"), None]);
+            assert_eq!(eager[0].command.as_ref().unwrap().args, ["-r"]);
+        } else {
+            // The continued lines are invalid rules on their own
+            assert_eq!(eager[0].to_string(), "null root:root 666");
+            assert_eq!(labels, [None, None]);
+        }
+    }
+
+    #[test]
+    fn lazy_defers_regex_errors() {
+        let lazy = parse_lazy("sd[a root:disk 660\n");
+        assert_eq!(lazy.len(), 2);
+        assert!(lazy[0].get().is_err());
+        assert!(lazy[1].get().is_ok());
+    }
+}
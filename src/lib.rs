@@ -1,16 +1,88 @@
 #[macro_use]
 extern crate pest_derive;
 use pest::{iterators::Pair, Parser};
-use regex::Regex;
+use regex::{Captures, Regex, RegexBuilder};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::iter::once;
-use std::{fmt::Display, num::ParseIntError};
-use tracing::error;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::{fmt::Display, io, ops::Range};
+use tracing::{error, warn};
+
+mod action;
+mod chain;
+mod coalesce;
+mod comments;
+mod continuation;
+mod diff;
+mod digest;
+mod error;
+mod escape;
+mod expand;
+#[cfg(feature = "fs-check")]
+mod fs_check;
+#[cfg(feature = "arbitrary")]
+mod generator;
+mod glob;
+mod grammar;
+mod label;
+mod lazy;
+mod lint;
+mod matcher;
+mod matching;
+mod mode;
+mod normalize;
+mod options;
+mod presets;
+mod query;
+mod stream;
+mod table;
+#[cfg(any(test, feature = "test-util"))]
+mod test_util;
+mod vars;
+
+pub use action::{resolve_action, DeviceAction};
+pub use chain::{lint_no_terminal_stop, StopWarning};
+pub use coalesce::{coalesce_majmin, find_majmin_overlaps};
+pub use comments::{parse_with_comments, render, ConfLine};
+pub use diff::{diff, rule_sets_equivalent, ConfDiff};
+pub use digest::rule_set_digest;
+pub use error::{LoadError, ParseError, ParseErrorKind};
+#[cfg(feature = "fs-check")]
+pub use fs_check::lint_missing_commands;
+pub use grammar::{grammar_rules, parse_rule_pair, validate_grammar, GRAMMAR};
+pub use lazy::{parse_lazy, LazyConf};
+pub use lint::{
+    dead_rules, lint_regex, lint_relative_commands, validate_mdev_regex, RegexWarning,
+    RegexWarningKind, Severity, SubstitutionIssue, ValidationIssue,
+};
+pub use matcher::Matcher;
+pub use matching::{all_matches, first_match_index, resolve_all, MatchContext, RuleSet};
+pub use mode::{format_mode, mode_from_octal_str};
+pub use options::{Dialect, MatchOptions, ParseOptions, Policy};
+pub use presets::{DeviceType, FilterPreset};
+pub use query::{referenced_groups, referenced_users, rules_invoking, rules_invoking_under};
+pub use stream::{ParseEvent, StreamParser};
+pub use table::format_table;
+pub use vars::MDEV_VARS;
+
+use escape::escape_regex;
+use glob::{glob_from_rule, glob_to_regex, GLOB_PREFIX};
+use label::LABEL_DIRECTIVE;
+use lint::shell_substitution;
+use mode::OctalMode;
+use options::Context;
 
 #[derive(Parser)]
 #[grammar = "../assets/conf_grammar.pest"]
-struct ConfParser;
+/// The [pest] parser of the configuration grammar, see [`GRAMMAR`]
+pub struct ConfParser;
+
+/// Mode used by [`Conf::default()`]
+const DEFAULT_MODE: u32 = 0o660;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 /// A line in the configuration file
 pub struct Conf {
     /// Whether to stop is this filter matches
@@ -18,25 +90,38 @@ pub struct Conf {
     pub envmatches: Vec<EnvMatch>,
     /// Filter used to match the devices
     pub filter: Filter,
-    /// User that will own the device
+    /// User that will own the device.
+    ///
+    /// With the `extensions` feature the user can be left out, as in `:disk`, and is then `root`
+    /// like for [`Conf::default()`]. The rule is written back with the user, as `root:disk`.
     pub user: String,
     /// Group that will own the device
     pub group: String,
-    /// Permissions that the specified user and group have on the device
+    /// Permissions that the specified user and group have on the device.
+    ///
+    /// With the `extensions` feature the mode can be omitted, in which case it is set to the
+    /// mode of [`Conf::default()`].
     pub mode: u32,
     /// What to do with the device node, if [`None`] it gets placed in `/dev/` with its
     /// original name
     pub on_creation: Option<OnCreation>,
     /// Additional command that has to be executed when creating and/or removing the node
     pub command: Option<Command>,
+    /// Name given to the rule by a `#name: label` comment on the line before it, with the
+    /// `extensions` feature. The comment is written back before the rule by
+    /// [`Display`](std::fmt::Display).
+    pub label: Option<String>,
 }
 
 impl Conf {
-    fn from_rule(v: Pair<'_, Rule>) -> anyhow::Result<Self> {
-        debug_assert_eq!(v.as_rule(), Rule::rule);
+    fn from_rule(v: Pair<'_, Rule>, ctx: &mut Context<'_>) -> Result<Self, ParseError> {
+        debug_assert!(matches!(v.as_rule(), Rule::rule | Rule::legacy_rule));
         let mut conf = v.into_inner();
         let matcher = conf.next().unwrap();
-        debug_assert_eq!(matcher.as_rule(), Rule::matcher);
+        debug_assert!(matches!(
+            matcher.as_rule(),
+            Rule::matcher | Rule::legacy_matcher
+        ));
         let mut matcher = matcher.into_inner();
         let stop = matcher
             .peek()
@@ -47,24 +132,54 @@ impl Conf {
         }
         let mut envmatches = Vec::new();
         while matcher.peek().unwrap().as_rule() == Rule::env_match {
-            let envmatch = EnvMatch::from_rule(matcher.next().unwrap())?;
+            let envmatch = EnvMatch::from_rule(matcher.next().unwrap(), ctx)?;
             envmatches.push(envmatch);
         }
         let filter = matcher.next().unwrap();
         let filter = match filter.as_rule() {
-            Rule::majmin => Filter::MajMin(MajMin::from_rule(filter)?),
-            Rule::device_regex => Filter::DeviceRegex(DeviceRegex::from_rule(filter)?),
+            Rule::majmin => Filter::MajMin(MajMin::from_rule(filter, ctx)?),
+            Rule::majmin_list => {
+                require_extensions(span_of(&filter), "listing several majmin ranges")?;
+                let list = filter
+                    .into_inner()
+                    .map(|majmin| MajMin::from_rule(majmin, ctx));
+                Filter::MajMinList(list.collect::<Result<_, _>>()?)
+            }
+            Rule::device_regex => Filter::DeviceRegex(DeviceRegex::from_rule(filter, ctx)?),
             _ => unreachable!(),
         };
-        let (user, group) = user_group_from_rule(conf.next().unwrap());
-        let mode = mode_from_rule(conf.next().unwrap());
+        let usergroup = conf.next().unwrap();
+        let (user, group, mode) = if usergroup.as_rule() == Rule::packed {
+            require_extensions(span_of(&usergroup), "packing the mode with the owner")?;
+            let mut packed = usergroup.into_inner();
+            let (user, group) = user_group_from_rule(packed.next().unwrap())?;
+            (user, group, mode_from_rule(packed.next().unwrap())?)
+        } else {
+            let usergroup_end = usergroup.as_span().end();
+            let (user, group) = user_group_from_rule(usergroup)?;
+            let mode = match conf.peek() {
+                Some(mode) if mode.as_rule() == Rule::mode => mode_from_rule(conf.next().unwrap())?,
+                Some(mode) if mode.as_rule() == Rule::spaced_mode => {
+                    spaced_mode_from_rule(conf.next().unwrap(), ctx)?
+                }
+                _ => {
+                    require_extensions(usergroup_end..usergroup_end, "omitting the mode")?;
+                    DEFAULT_MODE
+                }
+            };
+            (user, group, mode)
+        };
 
         let (on_creation, command) = match conf.next() {
             Some(next) if next.as_rule() == Rule::on_creation => (
                 Some(OnCreation::from_rule(next)),
-                conf.next().map(Command::from_rule),
+                conf.next()
+                    .map(|command| Command::from_rule(command, ctx))
+                    .transpose()?,
             ),
-            Some(next) if next.as_rule() == Rule::command => (None, Some(Command::from_rule(next))),
+            Some(next) if next.as_rule() == Rule::command => {
+                (None, Some(Command::from_rule(next, ctx)?))
+            }
             None => (None, None),
             _ => unreachable!(),
         };
@@ -77,49 +192,102 @@ impl Conf {
             mode,
             on_creation,
             command,
+            // Set by the caller, which knows the previous line
+            label: None,
         })
     }
+
+    /// The command to run during `phase`, if any
+    pub fn command_for(&self, phase: Phase) -> Option<&Command> {
+        self.command.as_ref().filter(|c| c.when.covers(phase))
+    }
+
+    /// Whether anything happens when a device matched by the rule appears: a node is created,
+    /// possibly moved or linked, or a command runs in the [`Phase::Add`] phase.
+    ///
+    /// Only a rule preventing the creation of the node without a command for the add phase does
+    /// nothing.
+    pub fn triggers_on_add(&self) -> bool {
+        self.on_creation != Some(OnCreation::Prevent) || self.command_for(Phase::Add).is_some()
+    }
+
+    /// Path where the node of the device named `device_name` will be created.
+    ///
+    /// `caps` are the capture groups of the device regex, used to replace `%0`–`%9` in the
+    /// target of [`OnCreation::Move`] and [`OnCreation::SymLink`]. Returns [`None`] if the
+    /// creation of the node is prevented.
+    pub fn target_path(&self, device_name: &str, caps: Option<&Captures<'_>>) -> Option<PathBuf> {
+        let name = match &self.on_creation {
+            Some(on_creation) => on_creation.expand(device_name, caps)?,
+            None => device_name.to_string(),
+        };
+        Some(Path::new("/dev").join(name))
+    }
+
+    /// The same rule applied to the devices matched by `filter`, keeping the envmatches, the
+    /// owner, the mode, the action and the command
+    pub fn with_filter(self, filter: Filter) -> Conf {
+        Conf { filter, ..self }
+    }
+
+    /// Same as [`Conf::with_filter`] with a regex matching the device name, which must be valid
+    /// for the configuration to be written back: it cannot contain whitespace.
+    pub fn with_device_regex(self, pattern: &str) -> Result<Conf, regex::Error> {
+        let regex = Regex::new(pattern)?;
+        Ok(self.with_filter(Filter::DeviceRegex(DeviceRegex::new(None, regex))))
+    }
 }
 
 impl Display for Conf {
+    /// Formats the rule in the configuration syntax, or as a labeled multi-line breakdown when
+    /// the alternate flag (`{:#}`) is used.
+    ///
+    /// Regexes are escaped where needed, so that the rule is parsed back to an equal [`Conf`].
+    /// The configuration syntax has no way to escape the other fields, so names, paths and
+    /// arguments containing whitespace, or characters not allowed by the grammar, are written as
+    /// they are and the line will not parse. With the `extensions` feature, a last argument
+    /// ending with `\` continues the rule on the next line when parsed.
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            if let Some(label) = &self.label {
+                writeln!(f, "label: {}", label)?;
+            }
+            writeln!(f, "stop: {}", self.stop)?;
+            for envmatch in &self.envmatches {
+                writeln!(f, "envmatch: {}", envmatch)?;
+            }
+            writeln!(f, "filter: {}", self.filter)?;
+            writeln!(f, "owner: {}:{}", self.user, self.group)?;
+            write!(f, "mode: {}", self.mode_octal_string())?;
+            if let Some(on_creation) = &self.on_creation {
+                write!(f, "\non_creation: {}", on_creation)?;
+            }
+            if let Some(command) = &self.command {
+                write!(
+                    f,
+                    "\ncommand: {}{} args {:?}",
+                    command.when, command.path, command.args
+                )?;
+            }
+            return Ok(());
+        }
+
+        if let Some(label) = &self.label {
+            writeln!(f, "{} {}", LABEL_DIRECTIVE, label)?;
+        }
         if !self.stop {
             write!(f, "-")?;
         }
         for envmatch in &self.envmatches {
-            write!(f, "{}={};", envmatch.envvar, envmatch.regex)?;
+            write!(f, "{};", envmatch)?;
         }
-        match &self.filter {
-            Filter::DeviceRegex(DeviceRegex {
-                regex,
-                envvar: Some(var),
-            }) => write!(f, "${}={}", var, regex),
-            Filter::DeviceRegex(v) => write!(f, "{}", v.regex),
-            Filter::MajMin(MajMin {
-                maj,
-                min,
-                min2: Some(min2),
-            }) => write!(f, "@{},{}-{}", maj, min, min2),
-            Filter::MajMin(v) => write!(f, "@{},{}", v.maj, v.min),
-        }?;
-        write!(f, " {}:{} {:03o}", self.user, self.group, self.mode,)?;
+        write!(f, "{}", self.filter)?;
+        write!(f, " {}:{} {}", self.user, self.group, OctalMode(self.mode))?;
         if let Some(on_creation) = &self.on_creation {
-            match on_creation {
-                OnCreation::Move(p) => write!(f, " ={}", p),
-                OnCreation::SymLink(p) => write!(f, " >{}", p),
-                OnCreation::Prevent => write!(f, " !"),
-            }?;
+            write!(f, " {}", on_creation)?;
         }
         if let Some(command) = &self.command {
-            let when = match command.when {
-                WhenToRun::After => '@',
-                WhenToRun::Before => '$',
-                WhenToRun::Both => '*',
-            };
-            write!(f, " {}{}", when, command.path)?;
-            for arg in &command.args {
-                write!(f, " {}", arg)?;
-            }
+            write!(f, " {}", command)?;
         }
         Ok(())
     }
@@ -127,50 +295,132 @@ impl Display for Conf {
 
 impl Default for Conf {
     fn default() -> Self {
-        let filter = Filter::DeviceRegex(DeviceRegex {
-            envvar: None,
-            regex: Regex::new(".*").unwrap(),
-        });
+        let filter = Filter::DeviceRegex(DeviceRegex::new(None, Regex::new(".*").unwrap()));
         Conf {
             stop: false,
             envmatches: vec![],
             filter,
             user: "root".to_string(),
             group: "root".to_string(),
-            mode: 0o660,
+            mode: DEFAULT_MODE,
             on_creation: None,
             command: None,
+            label: None,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+/// A condition on an environment variable, that has to be satisfied for the rule to apply
 pub struct EnvMatch {
+    /// Name of the environment variable
     pub envvar: String,
+    /// [`Regex`] that has to match the value of the variable
     pub regex: Regex,
+    /// Whether the condition is inverted, written as `VAR!=regex`.
+    ///
+    /// Parsing negated envmatches requires the `extensions` feature.
+    pub negated: bool,
 }
 
 impl EnvMatch {
-    fn from_rule(v: Pair<'_, Rule>) -> Result<Self, regex::Error> {
+    /// Creates a (non negated) envmatch testing `envvar` with `regex`
+    pub fn new(envvar: impl Into<String>, regex: Regex) -> Self {
+        Self {
+            envvar: envvar.into(),
+            regex,
+            negated: false,
+        }
+    }
+
+    /// Like [`EnvMatch::new`], compiling `pattern`
+    pub fn try_new(envvar: impl Into<String>, pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self::new(envvar, Regex::new(pattern)?))
+    }
+
+    fn from_rule(v: Pair<'_, Rule>, ctx: &Context<'_>) -> Result<Self, ParseError> {
         debug_assert_eq!(v.as_rule(), Rule::env_match);
         let mut envmatch = v.into_inner();
         let envvar = envvar_from_rule(envmatch.next().unwrap()).into();
-        let regex = regex_from_rule(envmatch.next().unwrap())?;
-        Ok(Self { envvar, regex })
+        let mut next = envmatch.next().unwrap();
+        let negated = next.as_rule() == Rule::negate;
+        if negated {
+            require_extensions(span_of(&next), "negating an envmatch")?;
+            next = envmatch.next().unwrap();
+        }
+        let regex = regex_from_rule(next, ctx)?;
+        Ok(Self {
+            envvar,
+            regex,
+            negated,
+        })
+    }
+}
+
+impl Display for EnvMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let negate = if self.negated { "!" } else { "" };
+        let regex = escape_regex(self.regex.as_str(), false);
+        write!(f, "{}{}={}", self.envvar, negate, regex)
     }
 }
 
 impl PartialEq for EnvMatch {
     fn eq(&self, other: &Self) -> bool {
-        self.envvar == other.envvar && self.regex.as_str() == other.regex.as_str()
+        self.envvar == other.envvar
+            && escape_regex(self.regex.as_str(), false) == escape_regex(other.regex.as_str(), false)
+            && self.negated == other.negated
     }
 }
 
-#[derive(Debug, PartialEq)]
+impl Eq for EnvMatch {}
+
+impl Hash for EnvMatch {
+    /// Hashes the regex in its escaped form, consistently with [`PartialEq`]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.envvar.hash(state);
+        escape_regex(self.regex.as_str(), false).hash(state);
+        self.negated.hash(state);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 /// Filter used for matching the devices
 pub enum Filter {
     DeviceRegex(DeviceRegex),
     MajMin(MajMin),
+    /// Several majmin ranges, matching the devices in any of them, written separated by spaces
+    /// like `@8,0 @8,16`. This requires the `extensions` feature.
+    MajMinList(Vec<MajMin>),
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Filter::DeviceRegex(DeviceRegex {
+                envvar: Some(var),
+                glob: Some(glob),
+                ..
+            }) => write!(f, "${}={}{}", var, GLOB_PREFIX, glob),
+            Filter::DeviceRegex(DeviceRegex {
+                glob: Some(glob), ..
+            }) => write!(f, "{}{}", GLOB_PREFIX, glob),
+            Filter::DeviceRegex(DeviceRegex {
+                regex,
+                envvar: Some(var),
+                ..
+            }) => write!(f, "${}={}", var, escape_regex(regex.as_str(), false)),
+            Filter::DeviceRegex(v) => write!(f, "{}", escape_regex(v.regex.as_str(), true)),
+            Filter::MajMin(v) => write!(f, "{}", v),
+            Filter::MajMinList(list) => {
+                for (i, majmin) in list.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { " " };
+                    write!(f, "{}{}", sep, majmin)?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 impl From<DeviceRegex> for Filter {
@@ -185,60 +435,159 @@ impl From<MajMin> for Filter {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 /// A regex used for matching devices based on their names
 pub struct DeviceRegex {
     pub envvar: Option<String>,
     /// [`Regex`] used for matching
     pub regex: Regex,
+    /// Shell glob the regex was translated from, for filters written as `glob:pattern`
+    pub glob: Option<String>,
+    /// [`DeviceRegex::regex`] anchored to match whole strings, compiled the first time it is
+    /// needed
+    anchored: OnceLock<Regex>,
 }
 
 impl DeviceRegex {
-    fn from_rule(v: Pair<'_, Rule>) -> Result<Self, regex::Error> {
+    /// Creates a filter matching `regex` against the device name, or against the value of
+    /// `envvar` if present
+    pub fn new(envvar: Option<String>, regex: Regex) -> Self {
+        Self {
+            envvar,
+            regex,
+            glob: None,
+            anchored: OnceLock::new(),
+        }
+    }
+
+    /// Like [`DeviceRegex::new`], compiling `pattern`
+    pub fn try_new(envvar: Option<String>, pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self::new(envvar, Regex::new(pattern)?))
+    }
+
+    fn from_rule(v: Pair<'_, Rule>, ctx: &Context<'_>) -> Result<Self, ParseError> {
         debug_assert_eq!(v.as_rule(), Rule::device_regex);
         let mut devregex = v.into_inner();
         let envvar = devregex.next().unwrap();
         let (envvar, regex) = match envvar.as_rule() {
             Rule::envvar => (
                 Some(envvar_from_rule(envvar).into()),
-                regex_from_rule(devregex.next().unwrap())?,
+                devregex.next().unwrap(),
             ),
-            Rule::regex => (None, regex_from_rule(envvar)?),
+            Rule::regex => (None, envvar),
             _ => unreachable!(),
         };
-        Ok(Self { envvar, regex })
+        match glob_from_rule(&regex) {
+            Some(glob) => Ok(Self {
+                glob: Some(glob.into()),
+                ..Self::new(
+                    envvar,
+                    compile_regex(&glob_to_regex(glob), span_of(&regex), ctx.options)?,
+                )
+            }),
+            None => Ok(Self::new(envvar, regex_from_rule(regex, ctx)?)),
+        }
     }
 }
 
 impl PartialEq for DeviceRegex {
     fn eq(&self, other: &Self) -> bool {
-        self.envvar == other.envvar && self.regex.as_str() == other.regex.as_str()
+        let device = self.envvar.is_none();
+        self.envvar == other.envvar
+            && self.glob == other.glob
+            && escape_regex(self.regex.as_str(), device)
+                == escape_regex(other.regex.as_str(), device)
+    }
+}
+
+impl Eq for DeviceRegex {}
+
+impl Hash for DeviceRegex {
+    /// Hashes the regex in its escaped form, consistently with [`PartialEq`]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.envvar.hash(state);
+        self.glob.hash(state);
+        escape_regex(self.regex.as_str(), self.envvar.is_none()).hash(state);
     }
 }
 
-#[derive(Debug, PartialEq)]
-/// TODO: add docs
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+/// Major and minor numbers of the devices to match, written `@maj,min` for a single device or
+/// `@maj,min-min2` for the inclusive range of minors from `min` to `min2`
 pub struct MajMin {
     pub maj: u32,
     pub min: u32,
     pub min2: Option<u32>,
 }
 
+impl Display for MajMin {
+    /// Formats the numbers in decimal, like `@8,16-31`, or in hexadecimal when the alternate
+    /// flag (`{:#}`) is used, like `@0x8,0x10-0x1f`, which requires the `extensions` feature to
+    /// be parsed back.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            write!(f, "@{:#x},{:#x}", self.maj, self.min)?;
+            return match self.min2 {
+                Some(min2) => write!(f, "-{:#x}", min2),
+                None => Ok(()),
+            };
+        }
+        match self.min2 {
+            Some(min2) => write!(f, "@{},{}-{}", self.maj, self.min, min2),
+            None => write!(f, "@{},{}", self.maj, self.min),
+        }
+    }
+}
+
 impl MajMin {
-    fn from_rule(v: Pair<'_, Rule>) -> anyhow::Result<Self> {
+    /// Largest major number supported by the kernel, which uses 12 bits for it
+    pub const MAX_MAJOR: u32 = (1 << 12) - 1;
+    /// Largest minor number supported by the kernel, which uses 20 bits for it
+    pub const MAX_MINOR: u32 = (1 << 20) - 1;
+
+    /// Matches the `count` consecutive minors starting at `min`, for a device class using one
+    /// minor per device.
+    ///
+    /// This is stored and written like the `@maj,min-min2` form, with `min2` being the last minor
+    /// of the range rather than the number of devices: `with_count(8, 16, 16)` is `@8,16-31`.
+    /// A count of 1 gives the single device form `@maj,min`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is 0.
+    pub fn with_count(maj: u32, min: u32, count: u32) -> Self {
+        assert!(count > 0, "a majmin must match at least one device");
+        let min2 = (count > 1).then(|| min.saturating_add(count - 1));
+        Self { maj, min, min2 }
+    }
+
+    fn from_rule(v: Pair<'_, Rule>, ctx: &mut Context<'_>) -> Result<Self, ParseError> {
         debug_assert_eq!(v.as_rule(), Rule::majmin);
         let mut majmin = v.into_inner();
-        let maj = u32_from_rule(majmin.next().unwrap())?;
-        let min = u32_from_rule(majmin.next().unwrap())?;
-        let min2 = majmin.next().map(u32_from_rule).transpose()?;
+        let maj = device_number_from_rule(majmin.next().unwrap(), Self::MAX_MAJOR, ctx)?;
+        let min = device_number_from_rule(majmin.next().unwrap(), Self::MAX_MINOR, ctx)?;
+        let min2 = majmin
+            .next()
+            .map(|v| device_number_from_rule(v, Self::MAX_MINOR, ctx))
+            .transpose()?;
         Ok(Self { maj, min, min2 })
     }
+
+    /// Whether all the numbers are within the limits of the kernel
+    pub fn within_kernel_limits(&self) -> bool {
+        self.maj <= Self::MAX_MAJOR
+            && self.min <= Self::MAX_MINOR
+            && self.min2.map_or(true, |min2| min2 <= Self::MAX_MINOR)
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 /// Additional actions to take on creation of the device node
 pub enum OnCreation {
     /// Moves/renames the device. If the path ends with `/` then the name will be stay the same
+    ///
+    /// A backslash is a literal filename character, not a separator, see
+    /// [`Conf::has_backslash_path`]
     Move(String),
     /// Same as [`OnCreation::Move`] but also creates a symlink in `/dev/` to the
     /// renamed/moved device
@@ -258,9 +607,52 @@ impl OnCreation {
             _ => unreachable!(),
         }
     }
+
+    /// Target of the move or the symlink, relative to `/dev/`.
+    ///
+    /// Every `%` followed by a digit is replaced with the corresponding group of `caps`, if it
+    /// took part in the match. Like mdev, a single digit is read, so `%10` is group 1 followed by
+    /// `0`. If the resulting path ends with `/`, `device_name` is appended to it. Returns
+    /// [`None`] for [`OnCreation::Prevent`].
+    pub fn expand(&self, device_name: &str, caps: Option<&Captures<'_>>) -> Option<String> {
+        let path = match self {
+            Self::Move(p) | Self::SymLink(p) => p,
+            Self::Prevent => return None,
+        };
+        let mut expanded = String::with_capacity(path.len());
+        let mut chars = path.chars().peekable();
+        while let Some(c) = chars.next() {
+            let group = chars
+                .peek()
+                .and_then(|d| d.to_digit(10))
+                .filter(|_| c == '%')
+                .and_then(|d| caps?.get(d as usize));
+            match group {
+                Some(group) => {
+                    expanded.push_str(group.as_str());
+                    chars.next();
+                }
+                None => expanded.push(c),
+            }
+        }
+        if expanded.ends_with('/') {
+            expanded.push_str(device_name);
+        }
+        Some(expanded)
+    }
+}
+
+impl Display for OnCreation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OnCreation::Move(p) => write!(f, "={}", p),
+            OnCreation::SymLink(p) => write!(f, ">{}", p),
+            OnCreation::Prevent => write!(f, "!"),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 /// When to run the [`Command`]
 pub enum WhenToRun {
     /// After creating the device
@@ -271,7 +663,24 @@ pub enum WhenToRun {
     Both,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Phase of the lifetime of a device, as reported by the `ACTION` of a uevent
+pub enum Phase {
+    /// The device has been added
+    Add,
+    /// The device is being removed
+    Remove,
+}
+
 impl WhenToRun {
+    /// Whether a command with this [`WhenToRun`] runs during `phase`
+    pub fn covers(&self, phase: Phase) -> bool {
+        matches!(
+            (self, phase),
+            (Self::Both, _) | (Self::After, Phase::Add) | (Self::Before, Phase::Remove)
+        )
+    }
+
     fn from_rule(v: Pair<'_, Rule>) -> Self {
         debug_assert_eq!(v.as_rule(), Rule::when);
         match v.into_inner().next().unwrap().as_rule() {
@@ -283,25 +692,66 @@ impl WhenToRun {
     }
 }
 
-#[derive(Debug, PartialEq)]
+impl Display for WhenToRun {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let when = match self {
+            WhenToRun::After => '@',
+            WhenToRun::Before => '$',
+            WhenToRun::Both => '*',
+        };
+        write!(f, "{}", when)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Command {
     /// When to run the command
     pub when: WhenToRun,
     /// Path to the executable
     pub path: String,
-    /// Command line arguments
+    /// Command line arguments.
+    ///
+    /// The arguments are separated by whitespace, so parsed arguments are never empty and
+    /// whitespace after the last one adds nothing. An empty argument cannot be written in the
+    /// configuration: it is lost when the rule is formatted and parsed again.
     pub args: Vec<String>,
 }
 
 impl Command {
-    fn from_rule(v: Pair<'_, Rule>) -> Self {
+    fn from_rule(v: Pair<'_, Rule>, ctx: &mut Context<'_>) -> Result<Self, ParseError> {
         debug_assert_eq!(v.as_rule(), Rule::command);
         let mut command = v.into_inner();
         let mut exec = command.next().unwrap().into_inner();
         let when = WhenToRun::from_rule(exec.next().unwrap());
-        let path = path_from_rule(exec.next().unwrap()).into();
-        let args = command.map(arg_from_rule).map(String::from).collect();
-        Self { when, path, args }
+        let path = exec.next().unwrap();
+        let args: Vec<_> = command.collect();
+        for word in args.iter().chain(Some(&path)) {
+            if let Some(range) = shell_substitution(word.as_str()) {
+                let start = word.as_span().start();
+                let span = start + range.start..start + range.end;
+                let err = ParseError::new(span, ParseErrorKind::ShellSubstitution);
+                ctx.check(ctx.options.shell_substitution, err)?;
+            }
+        }
+        Ok(Self {
+            when,
+            path: path_from_rule(path).into(),
+            args: args
+                .into_iter()
+                .map(arg_from_rule)
+                .map(String::from)
+                .collect(),
+        })
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.when, self.path)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
     }
 }
 
@@ -325,48 +775,415 @@ fn envvar_from_rule(v: Pair<'_, Rule>) -> &str {
     v.as_str()
 }
 
-fn regex_from_rule(v: Pair<'_, Rule>) -> Result<Regex, regex::Error> {
+fn span_of(v: &Pair<'_, Rule>) -> Range<usize> {
+    v.as_span().start()..v.as_span().end()
+}
+
+/// Fails unless the `extensions` feature is enabled, `what` describes the extension being used.
+fn require_extensions(span: Range<usize>, what: &'static str) -> Result<(), ParseError> {
+    if cfg!(feature = "extensions") {
+        Ok(())
+    } else {
+        Err(ParseError::new(span, ParseErrorKind::Extension(what)))
+    }
+}
+
+/// Compiles `pattern`, found at `span`, within the limits set by `options`
+fn compile_regex(
+    pattern: &str,
+    span: Range<usize>,
+    options: &ParseOptions,
+) -> Result<Regex, ParseError> {
+    let regex = match options.regex_size_limit {
+        Some(limit) => RegexBuilder::new(pattern)
+            .size_limit(limit)
+            .dfa_size_limit(limit)
+            .build(),
+        None => Regex::new(pattern),
+    };
+    regex.map_err(|err| ParseError::new(span, ParseErrorKind::Regex(err)))
+}
+
+fn regex_from_rule(v: Pair<'_, Rule>, ctx: &Context<'_>) -> Result<Regex, ParseError> {
     debug_assert_eq!(v.as_rule(), Rule::regex);
-    Regex::new(v.as_str())
+    compile_regex(v.as_str(), span_of(&v), ctx.options)
 }
 
-fn u32_from_rule(v: Pair<'_, Rule>) -> Result<u32, ParseIntError> {
+fn u32_from_rule(v: Pair<'_, Rule>) -> Result<u32, ParseError> {
     debug_assert_eq!(v.as_rule(), Rule::number);
-    v.as_str().parse()
+    let s = v.as_str();
+    let value = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(digits) => {
+            require_extensions(span_of(&v), "writing device numbers in hexadecimal")?;
+            u32::from_str_radix(digits, 16)
+        }
+        None => s.parse(),
+    };
+    value.map_err(|err| ParseError::new(span_of(&v), ParseErrorKind::Number(err)))
+}
+
+fn device_number_from_rule(
+    v: Pair<'_, Rule>,
+    max: u32,
+    ctx: &mut Context<'_>,
+) -> Result<u32, ParseError> {
+    let span = span_of(&v);
+    let value = u32_from_rule(v)?;
+    if value > max {
+        let err = ParseError::new(span, ParseErrorKind::DeviceNumber { value, max });
+        ctx.check(ctx.options.device_numbers, err)?;
+    }
+    Ok(value)
 }
 
-fn user_group_from_rule(v: Pair<'_, Rule>) -> (String, String) {
+/// Parses the owner, defaulting to the user of [`Conf::default()`] when only the group is given
+fn user_group_from_rule(v: Pair<'_, Rule>) -> Result<(String, String), ParseError> {
     debug_assert_eq!(v.as_rule(), Rule::usergroup);
-    let mut usergroup = v.into_inner();
-    let user = name_from_rule(usergroup.next().unwrap()).into();
-    let group = name_from_rule(usergroup.next().unwrap()).into();
-    (user, group)
+    let start = v.as_span().start();
+    let mut names: Vec<_> = v.into_inner().map(name_from_rule).collect();
+    let group = names.pop().unwrap().into();
+    let user = match names.pop() {
+        Some(user) => user.into(),
+        None => {
+            require_extensions(start..start + 1, "omitting the user")?;
+            Conf::default().user
+        }
+    };
+    Ok((user, group))
 }
 
-fn mode_from_rule(v: Pair<'_, Rule>) -> u32 {
+fn mode_from_rule(v: Pair<'_, Rule>) -> Result<u32, ParseError> {
     debug_assert_eq!(v.as_rule(), Rule::mode);
-    u32::from_str_radix(v.as_str(), 8).unwrap()
+    let start = v.as_span().start();
+    mode_from_octal_str(v.as_str()).map_err(|mut err| {
+        err.span = start + err.span.start..start + err.span.end;
+        err
+    })
+}
+
+/// Parses a mode with whitespace between the digits, if allowed by the options
+fn spaced_mode_from_rule(v: Pair<'_, Rule>, ctx: &mut Context<'_>) -> Result<u32, ParseError> {
+    debug_assert_eq!(v.as_rule(), Rule::spaced_mode);
+    let err = ParseError::new(span_of(&v), ParseErrorKind::SpacedMode);
+    if !ctx.options.coerce_spaced_mode {
+        return Err(err);
+    }
+    ctx.warnings.push(err);
+
+    let start = v.as_span().start();
+    let digits = v
+        .as_str()
+        .char_indices()
+        .filter(|(_, c)| !c.is_whitespace());
+    let mut mode = 0;
+    for (i, c) in digits {
+        let digit = c.to_digit(8).ok_or_else(|| {
+            ParseError::new(start + i..start + i + 1, ParseErrorKind::OctalDigit(c))
+        })?;
+        mode = mode * 8 + digit;
+    }
+    Ok(mode)
+}
+
+/// Parses a single line of the configuration.
+///
+/// `line_number` is only used for error reporting.
+fn parse_event(line_number: usize, line: &str, options: &ParseOptions) -> ParseEvent {
+    if let Some(max) = options.max_line_len.filter(|&max| line.len() > max) {
+        // Avoid copying the whole line in the error
+        let end = (0..=max).rev().find(|&i| line.is_char_boundary(i)).unwrap();
+        let kind = ParseErrorKind::LineTooLong {
+            len: line.len(),
+            max,
+        };
+        let err = ParseError::new(end..end, kind).at_line(line_number, &line[..end]);
+        return ParseEvent::Error(err);
+    }
+
+    let trimmed = line.trim_start();
+    if options.comment_char != '#' && trimmed.starts_with(options.comment_char) {
+        return ParseEvent::Comment(trimmed.trim_end().into());
+    }
+
+    let mut ctx = Context::new(options);
+    let mut inner = |line| -> Result<_, ParseError> {
+        let start = match options.dialect {
+            Dialect::Modern => Rule::line,
+            Dialect::Legacy => Rule::legacy_line,
+        };
+        let mut v = ConfParser::parse(start, line).map_err(ParseError::from_pest)?;
+        let content = v.next().unwrap().into_inner().next().unwrap();
+        Ok(match content.as_rule() {
+            Rule::rule | Rule::legacy_rule => ParseEvent::Rule(Conf::from_rule(content, &mut ctx)?),
+            Rule::comment => ParseEvent::Comment(content.as_str().trim_end().into()),
+            Rule::empty => ParseEvent::Blank,
+            _ => unreachable!(),
+        })
+    };
+    match inner(line) {
+        Ok(event) => {
+            for warning in ctx.warnings {
+                warn!("{}", warning.at_line(line_number, line));
+            }
+            event
+        }
+        Err(err) => ParseEvent::Error(err.at_line(line_number, line)),
+    }
+}
+
+/// Parses a single line, returning [`None`] for comments and empty lines.
+///
+/// `line_number` is only used for error reporting.
+fn parse_line(
+    line_number: usize,
+    line: &str,
+    options: &ParseOptions,
+) -> Result<Option<Conf>, ParseError> {
+    match parse_event(line_number, line, options) {
+        ParseEvent::Rule(conf) => Ok(Some(conf)),
+        ParseEvent::Error(err) => Err(err),
+        ParseEvent::Comment(_) | ParseEvent::Blank | ParseEvent::Continued => Ok(None),
+    }
+}
+
+/// Parses the rules of the configuration, skipping comments and blank lines but keeping the
+/// labels given by comments
+fn rules<'a>(
+    input: &'a str,
+    options: &ParseOptions,
+) -> impl Iterator<Item = Result<Conf, ParseError>> + 'a {
+    let mut parser = StreamParser::with_options(options.clone());
+    let mut lines = input.lines();
+    std::iter::from_fn(move || loop {
+        let event = match lines.next() {
+            Some(line) => parser.feed(line),
+            None => parser.finish()?,
+        };
+        match event {
+            ParseEvent::Rule(conf) => return Some(Ok(conf)),
+            ParseEvent::Error(err) => return Some(Err(err)),
+            ParseEvent::Comment(_) | ParseEvent::Blank | ParseEvent::Continued => {}
+        }
+    })
 }
 
 /// Parses every line of the configuration contained in `input` excluding invalid ones.
 pub fn parse(input: &str) -> Vec<Conf> {
-    let filter_map = |line| {
-        let mut v = ConfParser::parse(Rule::line, line)
-            .map_err(|err| error!("parsing error: {}", err))
-            .ok()?;
-        let rule = Some(v.next().unwrap().into_inner().next().unwrap())
-            .filter(|r| r.as_rule() == Rule::rule)?;
-        Conf::from_rule(rule)
-            .map_err(|err| error!("regex error: {}", err))
-            .ok()
-    };
-    input
-        .lines()
-        .filter_map(filter_map)
+    parse_with(input, &ParseOptions::default())
+}
+
+/// Same as [`parse`], using the given [`ParseOptions`].
+pub fn parse_with(input: &str, options: &ParseOptions) -> Vec<Conf> {
+    rules(input, options)
+        .filter_map(|conf| conf.map_err(|err| error!("parsing error: {}", err)).ok())
         .chain(once(Conf::default()))
         .collect()
 }
 
+/// Same as [`parse`], with the variables of `env` resolved in the commands, see
+/// [`Conf::resolve_vars`].
+///
+/// The references to variables missing from `env` are kept, so that mdev expands them at run
+/// time. Use [`parse`] to get the rules as written.
+pub fn parse_resolved(input: &str, env: &HashMap<String, String>) -> Vec<Conf> {
+    parse(input)
+        .iter()
+        .map(|conf| conf.resolve_vars(env))
+        .collect()
+}
+
+/// Same as [`parse`], but the rule appended at the end prevents the creation of the nodes of
+/// the devices not matched by the configuration, instead of creating them with the defaults.
+///
+/// The appended rule is `.* root:root 660 !`: it matches every device and stops, so it only
+/// applies when no other rule stopped the search. Unlike [`Conf::default()`] it is a normal
+/// rule, so it is written out by [`to_config_string`] and returned by
+/// [`RuleSet::first_match`] for the unmatched devices. A device matched only by rules with the
+/// `-` prefix reaches it as well, and its node is not created.
+pub fn parse_with_default_prevent(input: &str) -> Vec<Conf> {
+    let mut confs = parse(input);
+    strip_default(&mut confs);
+    confs.push(Conf {
+        stop: true,
+        on_creation: Some(OnCreation::Prevent),
+        ..Conf::default()
+    });
+    confs
+}
+
+/// Same as [`parse`], but fails on the first invalid line.
+///
+/// The returned [`ParseError`] points at the offending token of the line.
+pub fn parse_strict(input: &str) -> Result<Vec<Conf>, ParseError> {
+    parse_strict_with(input, &ParseOptions::default())
+}
+
+/// Same as [`parse_strict`], using the given [`ParseOptions`].
+pub fn parse_strict_with(input: &str, options: &ParseOptions) -> Result<Vec<Conf>, ParseError> {
+    let mut confs = rules(input, options).collect::<Result<Vec<_>, _>>()?;
+    confs.push(Conf::default());
+    Ok(confs)
+}
+
+/// Same as [`parse`], but also returns the errors found in the invalid lines.
+pub fn parse_collecting(input: &str) -> (Vec<Conf>, Vec<ParseError>) {
+    parse_collecting_with(input, &ParseOptions::default())
+}
+
+/// Same as [`parse_collecting`], using the given [`ParseOptions`].
+pub fn parse_collecting_with(input: &str, options: &ParseOptions) -> (Vec<Conf>, Vec<ParseError>) {
+    let mut confs = Vec::new();
+    let mut errors = Vec::new();
+    for conf in rules(input, options) {
+        match conf {
+            Ok(conf) => confs.push(conf),
+            Err(err) => errors.push(err),
+        }
+    }
+    confs.push(Conf::default());
+    (confs, errors)
+}
+
+/// Same as [`parse`], but returns the errors of all the invalid lines instead of the rules if
+/// there is any.
+///
+/// Unlike [`parse_strict`], which stops at the first invalid line, every error is reported. This
+/// all-or-nothing form may become the behaviour of [`parse`] in a future version.
+pub fn parse2(input: &str) -> Result<Vec<Conf>, Vec<ParseError>> {
+    parse2_with(input, &ParseOptions::default())
+}
+
+/// Same as [`parse2`], using the given [`ParseOptions`].
+pub fn parse2_with(input: &str, options: &ParseOptions) -> Result<Vec<Conf>, Vec<ParseError>> {
+    match parse_collecting_with(input, options) {
+        (confs, errors) if errors.is_empty() => Ok(confs),
+        (_, errors) => Err(errors),
+    }
+}
+
+/// Same as [`parse`], but also returns the number of invalid lines that were skipped.
+///
+/// Comments and blank lines are not counted. Use [`parse_collecting`] or [`parse_strict`] to
+/// find out what is wrong with the invalid lines.
+pub fn try_parse(input: &str) -> (Vec<Conf>, usize) {
+    let (confs, errors) = parse_collecting(input);
+    (confs, errors.len())
+}
+
+/// Decompresses a gzip-compressed configuration and parses it like [`parse_strict`].
+#[cfg(feature = "gzip")]
+pub fn parse_gz_reader<R: io::Read>(reader: R) -> Result<Vec<Conf>, LoadError> {
+    let mut input = String::new();
+    io::Read::read_to_string(&mut flate2::read::GzDecoder::new(reader), &mut input)?;
+    Ok(parse_strict(&input)?)
+}
+
+/// Parses the configuration file at `path` like [`parse_strict`], reading it through a memory
+/// map instead of loading it in a [`String`].
+///
+/// Comments are skipped without being decoded, while any other line that is not valid UTF-8
+/// gives a [`ParseErrorKind::Utf8`] error pointing at the first invalid byte.
+#[cfg(feature = "mmap")]
+pub fn parse_mmap(path: &Path) -> Result<Vec<Conf>, LoadError> {
+    let file = std::fs::File::open(path)?;
+    // SAFETY: the file is only read, and the map does not outlive this function. The
+    // configuration being modified while it is parsed can give wrong rules, but it cannot cause
+    // undefined behavior since every line is validated as UTF-8 before being parsed.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    let bytes = map.strip_prefix(stream::BOM.as_bytes()).unwrap_or(&map);
+    let bytes = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+
+    let options = ParseOptions::default();
+    let mut confs = Vec::new();
+    let mut labels = label::Labels::default();
+    let mut continuation = continuation::Continuation::default();
+    let mut handle = |mut event: ParseEvent| {
+        labels.apply(&mut event);
+        match event {
+            ParseEvent::Rule(conf) => confs.push(conf),
+            ParseEvent::Error(err) => return Err(err),
+            ParseEvent::Comment(_) | ParseEvent::Blank | ParseEvent::Continued => {}
+        }
+        Ok(())
+    };
+    for (i, line) in bytes.split(|&b| b == b'\n').enumerate() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let content = &line[line.iter().take_while(|b| b.is_ascii_whitespace()).count()..];
+        if content.starts_with(b"#") && !continuation.is_pending() {
+            // Only label directives are worth decoding
+            let comment = if content.starts_with(LABEL_DIRECTIVE.as_bytes()) {
+                String::from_utf8_lossy(content)
+                    .trim_end_matches(|c: char| c.is_ascii_whitespace())
+                    .to_string()
+            } else {
+                String::new()
+            };
+            handle(ParseEvent::Comment(comment))?;
+            continue;
+        }
+        let line = std::str::from_utf8(line).map_err(|err| {
+            let start = err.valid_up_to();
+            let span = start..start + char::REPLACEMENT_CHARACTER.len_utf8();
+            ParseError::new(span, ParseErrorKind::Utf8(err))
+                .at_line(i + 1, &String::from_utf8_lossy(line))
+        })?;
+        if let Some((line_number, line)) = continuation.push(i + 1, line, options.comment_char) {
+            handle(parse_event(line_number, &line, &options))?;
+        }
+    }
+    if let Some((line_number, line)) = continuation.finish() {
+        handle(parse_event(line_number, &line, &options))?;
+    }
+    confs.push(Conf::default());
+    Ok(confs)
+}
+
+/// Strips the trailing [`Conf::default()`] appended by [`parse`], if present.
+fn without_default(confs: &[Conf]) -> &[Conf] {
+    match confs.split_last() {
+        Some((last, rest)) if *last == Conf::default() => rest,
+        _ => confs,
+    }
+}
+
+/// Removes the trailing [`Conf::default()`] appended by [`parse`] and the other parsing
+/// functions, if present.
+///
+/// Together with [`ensure_default`], this makes the catch-all explicit regardless of which
+/// function produced the rules.
+pub fn strip_default(confs: &mut Vec<Conf>) {
+    let len = without_default(confs).len();
+    confs.truncate(len);
+}
+
+/// Appends [`Conf::default()`] as the last rule, unless it is already there.
+///
+/// This is the rule mdev applies to the devices not matched by the configuration.
+pub fn ensure_default(confs: &mut Vec<Conf>) {
+    if confs.last() != Some(&Conf::default()) {
+        confs.push(Conf::default());
+    }
+}
+
+/// Formats the rules in the configuration syntax, one per line.
+///
+/// The trailing [`Conf::default()`] appended by [`parse`] is not included, so that parsing the
+/// output again gives back the same rules.
+pub fn to_config_string(confs: &[Conf]) -> String {
+    without_default(confs)
+        .iter()
+        .map(|conf| format!("{}\n", conf))
+        .collect()
+}
+
+/// Same as [`to_config_string`], but writes the configuration to `writer`.
+pub fn write_conf<W: io::Write>(mut writer: W, confs: &[Conf]) -> io::Result<()> {
+    for conf in without_default(confs) {
+        writeln!(writer, "{}", conf)?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -382,20 +1199,7 @@ mod tests {
     }
 
     fn common_case(r: &str) -> Conf {
-        Conf {
-            stop: true,
-            envmatches: vec![],
-            filter: DeviceRegex {
-                envvar: None,
-                regex: regex(r),
-            }
-            .into(),
-            user: "root".into(),
-            group: "root".into(),
-            mode: 0o660,
-            on_creation: None,
-            command: None,
-        }
+        Conf::device(r, "root", "root", 0o660)
     }
 
     fn regex(s: &str) -> Regex {
@@ -405,8 +1209,8 @@ mod tests {
     in_out_test! {
         "SYSTEM=usb;DEVTYPE=usb_device;.*\troot:root\t660  */opt/dev-bus-usb" <===> Conf {
             envmatches: vec![
-                EnvMatch { envvar: "SYSTEM".into(), regex: regex("usb") },
-                EnvMatch { envvar: "DEVTYPE".into(), regex: regex("usb_device") },
+                EnvMatch { envvar: "SYSTEM".into(), regex: regex("usb"), negated: false },
+                EnvMatch { envvar: "DEVTYPE".into(), regex: regex("usb_device"), negated: false },
             ],
             command: Command {
                 when: WhenToRun::Both,
@@ -416,10 +1220,7 @@ mod tests {
             ..common_case(".*")
         },
         "$MODALIAS=.*\troot:root\t660 @modprobe -b \"$MODALIAS\" " <===> Conf {
-            filter: DeviceRegex {
-                envvar: Some("MODALIAS".into()),
-                regex: regex(".*"),
-            }.into(),
+            filter: DeviceRegex::new(Some("MODALIAS".into()), regex(".*")).into(),
             command: Command {
                 when: WhenToRun::After,
                 path: "modprobe".into(),
@@ -431,19 +1232,15 @@ mod tests {
             filter: MajMin { maj: 42, min: 17, min2: Some(125) }.into(),
             ..common_case(".*")
         },
-        "@42,17     root:root 660" <===> Conf {
-            filter: MajMin { maj: 42, min: 17, min2: None }.into(),
-            ..common_case(".*")
-        },
+        "@42,17     root:root 660" <===> Conf::majmin(42, 17),
         "loop([0-9]+)\troot:disk 660\t>loop/%1" <===> Conf {
-            user: "root".into(), group: "disk".into(),
             on_creation: OnCreation::SymLink("loop/%1".into()).into(),
-            ..common_case("loop([0-9]+)")
+            ..Conf::device("loop([0-9]+)", "root", "disk", 0o660)
         },
         "SUBSYSTEM=usb;DEVTYPE=usb_device;.* root:root 660 */opt/mdev/helpers/dev-bus-usb" <===> Conf {
             envmatches: vec![
-                EnvMatch { envvar: "SUBSYSTEM".into(), regex: regex("usb"), },
-                EnvMatch { envvar: "DEVTYPE".into(), regex: regex("usb_device"), },
+                EnvMatch { envvar: "SUBSYSTEM".into(), regex: regex("usb"), negated: false },
+                EnvMatch { envvar: "DEVTYPE".into(), regex: regex("usb_device"), negated: false },
             ],
             command: Command {
                 when: WhenToRun::Both,
@@ -455,8 +1252,8 @@ mod tests {
         "-SUBSYSTEM=net;DEVPATH=.*/net/.*;.*\troot:root 600 @/opt/mdev/helpers/settle-nics --write-mactab" <===> Conf {
             stop: false,
             envmatches: vec![
-                EnvMatch { envvar: "SUBSYSTEM".into(), regex: regex("net"), },
-                EnvMatch { envvar: "DEVPATH".into(), regex: regex(".*/net/.*"), },
+                EnvMatch { envvar: "SUBSYSTEM".into(), regex: regex("net"), negated: false },
+                EnvMatch { envvar: "DEVPATH".into(), regex: regex(".*/net/.*"), negated: false },
             ],
             mode: 0o600,
             command: Command {
@@ -467,7 +1264,7 @@ mod tests {
             ..common_case(".*")
         },
         "SUBSYSTEM=sound;.*  root:audio 660 @/opt/mdev/helpers/sound-control" <===> Conf {
-            envmatches: vec![EnvMatch { envvar: "SUBSYSTEM".into(), regex: regex("sound"), }],
+            envmatches: vec![EnvMatch { envvar: "SUBSYSTEM".into(), regex: regex("sound"), negated: false }],
             user: "root".into(), group: "audio".into(),
             command: Command {
                 when: WhenToRun::After,
@@ -482,7 +1279,7 @@ mod tests {
             ..common_case("cpu([0-9]+)")
         },
         "SUBSYSTEM=input;.* root:input 660" <===> Conf {
-            envmatches: vec![EnvMatch { envvar: "SUBSYSTEM".into(), regex: regex("input"), }],
+            envmatches: vec![EnvMatch { envvar: "SUBSYSTEM".into(), regex: regex("input"), negated: false }],
             user: "root".into(), group: "input".into(),
             ..common_case(".*")
         },
@@ -490,12 +1287,39 @@ mod tests {
             on_creation: OnCreation::Prevent.into(),
             ..common_case("[0-9]+:[0-9]+:[0-9]+:[0-9]+")
         },
+        "-@42,17 root:root 660" <===> Conf {
+            stop: false,
+            ..Conf::majmin(42, 17)
+        },
+        "-$MODALIAS=.* root:root 660" <===> Conf {
+            stop: false,
+            filter: DeviceRegex::new(Some("MODALIAS".into()), regex(".*")).into(),
+            ..common_case(".*")
+        },
+        "-SUBSYSTEM=block;@8,0-15 root:disk 640" <===> Conf {
+            stop: false,
+            envmatches: vec![EnvMatch { envvar: "SUBSYSTEM".into(), regex: regex("block"), negated: false }],
+            filter: MajMin { maj: 8, min: 0, min2: Some(15) }.into(),
+            user: "root".into(), group: "disk".into(),
+            mode: 0o640,
+            ..common_case(".*")
+        },
+        "null root:root 666 @/bin/notify \t" <===> Conf {
+            mode: 0o666,
+            command: Command {
+                when: WhenToRun::After,
+                path: "/bin/notify".into(),
+                args: vec![],
+            }.into(),
+            ..common_case("null")
+        },
     }
 
     #[test]
     fn test_all() {
         let conf = parse(INPUT);
         let hardcoded = outs();
+        assert_eq!(conf.len(), hardcoded.len() + 1);
 
         for (a, b) in conf.iter().zip(hardcoded.iter()) {
             assert_eq!(a, b);
@@ -508,4 +1332,789 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn trailing_whitespace() {
+        let conf = parse("$MODALIAS=.* root:root 660 @modprobe -b \"$MODALIAS\"  \t \n");
+        let command = conf[0].command.as_ref().unwrap();
+        assert_eq!(command.args, ["-b", "\"$MODALIAS\""]);
+        assert!(command.args.iter().all(|arg| !arg.is_empty()));
+        assert_eq!(
+            conf[0].to_string(),
+            "$MODALIAS=.* root:root 660 @modprobe -b \"$MODALIAS\""
+        );
+
+        // An empty argument cannot be written, so it does not survive a round trip
+        let mut with_empty = conf[0].clone();
+        with_empty
+            .command
+            .as_mut()
+            .unwrap()
+            .args
+            .push(String::new());
+        let reparsed = parse(&with_empty.to_string());
+        assert_eq!(reparsed[0], conf[0]);
+    }
+
+    #[test]
+    fn write_is_idempotent() {
+        let conf = parse(INPUT);
+        let written = to_config_string(&conf);
+        assert_eq!(written.lines().count(), INPUT.lines().count());
+        assert!(!written.contains("-.* root:root 660"));
+
+        let reparsed = parse(&written);
+        assert_eq!(conf, reparsed);
+        assert_eq!(written, to_config_string(&reparsed));
+
+        let mut buf = Vec::new();
+        write_conf(&mut buf, &conf).unwrap();
+        assert_eq!(buf, written.as_bytes());
+    }
+
+    #[test]
+    fn strict_error_points_at_regex() {
+        let err =
+            parse_strict("sda root:root 660\nSUBSYSTEM=block;sd[a root:disk 660\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(err.span, 16..20);
+        assert_eq!(err.column(), 17);
+        assert!(matches!(err.kind, ParseErrorKind::Regex(_)));
+        let rendered = err.to_string();
+        let mut lines = rendered.lines();
+        assert_eq!(lines.next(), Some("invalid regex at line 2, column 17"));
+        assert_eq!(lines.next(), Some("  |"));
+        assert_eq!(lines.next(), Some("2 | SUBSYSTEM=block;sd[a root:disk 660"));
+        assert_eq!(lines.next(), Some("  |                 ^^^^"));
+
+        let err = parse_strict("sda root:root 66").unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::Grammar(_)));
+        assert_eq!(err.column(), 15);
+    }
+
+    #[test]
+    fn target_path() {
+        let conf = parse("loop([0-9]+)\troot:disk 660\t>loop/%1\nsnd/(.*) root:audio 660 =sound/\nnull root:root 666 !\n");
+        let caps = regex("loop([0-9]+)").captures("loop7");
+        assert_eq!(
+            conf[0].target_path("loop7", caps.as_ref()),
+            Some("/dev/loop/7".into())
+        );
+        assert_eq!(
+            conf[1].target_path("pcm0", None),
+            Some("/dev/sound/pcm0".into())
+        );
+        assert_eq!(conf[2].target_path("null", None), None);
+        assert_eq!(conf[3].target_path("sda", None), Some("/dev/sda".into()));
+    }
+
+    #[test]
+    fn single_digit_groups() {
+        let target = OnCreation::Move("dev/%10".into());
+        let caps = regex("(sd)([a-z])").captures("sda");
+        assert_eq!(
+            target.expand("sda", caps.as_ref()).as_deref(),
+            Some("dev/sd0")
+        );
+
+        // Even with ten groups, %10 is not the tenth group
+        let caps = regex("(a)(b)(c)(d)(e)(f)(g)(h)(i)(j)").captures("abcdefghij");
+        assert_eq!(
+            target.expand("abcdefghij", caps.as_ref()).as_deref(),
+            Some("dev/a0")
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(INPUT.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(parse_gz_reader(&compressed[..]).unwrap(), parse(INPUT));
+
+        let err = parse_gz_reader(INPUT.as_bytes()).unwrap_err();
+        assert!(matches!(err, LoadError::Io(_)));
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"sd[a root:root 660\n").unwrap();
+        let compressed = encoder.finish().unwrap();
+        let err = parse_gz_reader(&compressed[..]).unwrap_err();
+        assert!(matches!(err, LoadError::Parse(_)));
+    }
+
+    #[test]
+    fn pretty_display() {
+        let conf = parse(INPUT);
+        assert_eq!(
+            format!("{}", conf[1]),
+            "$MODALIAS=.* root:root 660 @modprobe -b \"$MODALIAS\""
+        );
+        assert_eq!(
+            format!("{:#}", conf[1]),
+            "stop: true\n\
+             filter: $MODALIAS=.*\n\
+             owner: root:root\n\
+             mode: 0660\n\
+             command: @modprobe args [\"-b\", \"\\\"$MODALIAS\\\"\"]"
+        );
+        assert_eq!(
+            format!("{:#}", conf[6]),
+            "stop: false\n\
+             envmatch: SUBSYSTEM=net\n\
+             envmatch: DEVPATH=.*/net/.*\n\
+             filter: .*\n\
+             owner: root:root\n\
+             mode: 0600\n\
+             command: @/opt/mdev/helpers/settle-nics args [\"--write-mactab\"]"
+        );
+        assert_eq!(
+            format!("{:#}", conf[4]),
+            "stop: true\n\
+             filter: loop([0-9]+)\n\
+             owner: root:disk\n\
+             mode: 0660\n\
+             on_creation: >loop/%1"
+        );
+    }
+
+    #[test]
+    fn device_number_limits() {
+        let input =
+            "@4096,0 root:root 660\n@8,0-1048576 root:root 660\n@4095,1048575 root:root 660\n";
+        assert_eq!(parse_strict(input).unwrap().len(), 4);
+
+        let warn = ParseOptions {
+            device_numbers: Policy::Warn,
+            ..Default::default()
+        };
+        assert_eq!(parse_strict_with(input, &warn).unwrap().len(), 4);
+
+        let deny = ParseOptions {
+            device_numbers: Policy::Deny,
+            ..Default::default()
+        };
+        let err = parse_strict_with(input, &deny).unwrap_err();
+        assert_eq!(err.line, 1);
+        assert_eq!(err.span, 1..5);
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::DeviceNumber {
+                value: 4096,
+                max: MajMin::MAX_MAJOR
+            }
+        );
+        let conf = parse_with(input, &deny);
+        assert_eq!(conf.len(), 2);
+        assert!(conf.iter().all(|conf| match &conf.filter {
+            Filter::MajMin(majmin) => majmin.within_kernel_limits(),
+            Filter::MajMinList(list) => list.iter().all(MajMin::within_kernel_limits),
+            Filter::DeviceRegex(_) => true,
+        }));
+    }
+
+    #[test]
+    fn max_line_len() {
+        let long = format!("sdz root:root 660 @/bin/true {}", "a".repeat(1 << 20));
+        let input = format!("sda root:root 660\n{}\nsdb root:root 660\n", long);
+        assert_eq!(parse_strict(&input).unwrap().len(), 4);
+
+        let options = ParseOptions {
+            max_line_len: Some(4096),
+            ..Default::default()
+        };
+        let err = parse_strict_with(&input, &options).unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::LineTooLong {
+                len: long.len(),
+                max: 4096
+            }
+        );
+        assert_eq!(err.source_line.len(), 4096);
+
+        let (conf, errors) = parse_collecting_with(&input, &options);
+        assert_eq!(
+            conf,
+            [common_case("sda"), common_case("sdb"), Conf::default()]
+        );
+        assert_eq!(errors, [err]);
+    }
+
+    #[test]
+    fn default_rule() {
+        let mut conf = parse("sda root:root 660\n");
+        assert_eq!(conf.len(), 2);
+        strip_default(&mut conf);
+        assert_eq!(conf, [common_case("sda")]);
+        strip_default(&mut conf);
+        assert_eq!(conf, [common_case("sda")]);
+
+        ensure_default(&mut conf);
+        ensure_default(&mut conf);
+        assert_eq!(conf, parse("sda root:root 660\n"));
+
+        let mut conf = Vec::new();
+        strip_default(&mut conf);
+        assert!(conf.is_empty());
+        ensure_default(&mut conf);
+        assert_eq!(conf, [Conf::default()]);
+    }
+
+    #[test]
+    fn command_for() {
+        let conf = parse(INPUT);
+        // `*` runs in both phases
+        assert_eq!(conf[0].command_for(Phase::Add), conf[0].command.as_ref());
+        assert_eq!(conf[0].command_for(Phase::Remove), conf[0].command.as_ref());
+        // `@` runs after creation only
+        assert_eq!(conf[1].command_for(Phase::Add), conf[1].command.as_ref());
+        assert_eq!(conf[1].command_for(Phase::Remove), None);
+        // no command at all
+        assert_eq!(conf[2].command_for(Phase::Add), None);
+
+        let conf = parse("sda root:disk 660 $/bin/umount-all");
+        assert_eq!(conf[0].command_for(Phase::Add), None);
+        assert_eq!(conf[0].command_for(Phase::Remove), conf[0].command.as_ref());
+    }
+
+    #[test]
+    fn triggers_on_add() {
+        let conf = parse(
+            "\
+null root:root 666 !
+null root:root 666 ! @/bin/notify
+null root:root 666 ! $/bin/cleanup
+null root:root 666 ! */bin/notify
+sda root:disk 660
+sda root:disk 660 >disk/0
+sda root:disk 660 $/bin/umount-all
+",
+        );
+        let triggers: Vec<_> = conf.iter().map(Conf::triggers_on_add).collect();
+        assert_eq!(triggers, [false, true, false, true, true, true, true, true]);
+    }
+
+    #[test]
+    fn indented() {
+        let conf = parse_strict(
+            "\t  sda root:root 660\n    sda root:root 660\n\t-sda root:root 660\n  # comment\n",
+        )
+        .unwrap();
+        assert_eq!(conf.len(), 4);
+        assert_eq!(conf[0], common_case("sda"));
+        assert_eq!(conf[1], common_case("sda"));
+        assert_eq!(
+            conf[2],
+            Conf {
+                stop: false,
+                ..common_case("sda")
+            }
+        );
+    }
+
+    #[test]
+    fn negated_envmatch() {
+        let res = parse_strict("SUBSYSTEM!=block;.* root:root 660");
+        #[cfg(feature = "extensions")]
+        {
+            let conf = res.unwrap();
+            assert_eq!(
+                conf[0].envmatches,
+                [EnvMatch {
+                    envvar: "SUBSYSTEM".into(),
+                    regex: regex("block"),
+                    negated: true
+                }]
+            );
+            assert_eq!(conf[0].to_string(), "SUBSYSTEM!=block;.* root:root 660");
+        }
+        #[cfg(not(feature = "extensions"))]
+        {
+            let err = res.unwrap_err();
+            assert_eq!(err.kind, ParseErrorKind::Extension("negating an envmatch"));
+            assert_eq!(err.span, 9..10);
+        }
+    }
+
+    #[test]
+    fn invalid_mode() {
+        let err = parse_strict("sda root:root 680").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::OctalDigit('8'));
+        assert_eq!(err.span, 15..16);
+        assert!(err
+            .to_string()
+            .starts_with("invalid octal digit '8' in mode at line 1, column 16"));
+
+        let err = parse_strict("sda root:root 6 6 0").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::SpacedMode);
+    }
+
+    #[test]
+    fn constructors() {
+        let conf = &parse("SUBSYSTEM=block;$DEVNAME=sd[a-z] root:disk 660")[0];
+        assert_eq!(
+            conf.envmatches,
+            [EnvMatch::new("SUBSYSTEM", regex("block"))]
+        );
+        assert_eq!(
+            conf.filter,
+            Filter::DeviceRegex(DeviceRegex::new(Some("DEVNAME".into()), regex("sd[a-z]")))
+        );
+        assert_eq!(
+            EnvMatch::try_new("SUBSYSTEM", "block").unwrap(),
+            conf.envmatches[0]
+        );
+        assert_eq!(
+            Filter::from(DeviceRegex::try_new(Some("DEVNAME".into()), "sd[a-z]").unwrap()),
+            conf.filter
+        );
+        assert!(EnvMatch::try_new("SUBSYSTEM", "block(").is_err());
+        assert!(DeviceRegex::try_new(None, "sd[a-z").is_err());
+    }
+
+    #[test]
+    fn skipped_lines() {
+        let (conf, skipped) =
+            try_parse("# disks\nsda root:disk 660\n\nsdb( root:disk 660\nsdc root disk 660\n");
+        assert_eq!(conf.len(), 2);
+        assert_eq!(skipped, 2);
+        assert_eq!(try_parse(INPUT).1, 0);
+    }
+
+    #[test]
+    fn omitted_mode() {
+        let res = parse_strict("sda root:root\nsdb root:root >disk/%0\n");
+        #[cfg(feature = "extensions")]
+        {
+            let conf = res.unwrap();
+            assert_eq!(conf[0], common_case("sda"));
+            assert_eq!(conf[1].mode, Conf::default().mode);
+            assert_eq!(conf[1].to_string(), "sdb root:root 660 >disk/%0");
+        }
+        #[cfg(not(feature = "extensions"))]
+        assert_eq!(
+            res.unwrap_err().kind,
+            ParseErrorKind::Extension("omitting the mode")
+        );
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap() {
+        let dir = std::env::temp_dir().join(format!("mdev-parser-mmap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("mdev.conf");
+
+        let mut input = INPUT.as_bytes().to_vec();
+        input.extend_from_slice(b"\n# caf\xe9\r\nsdz root:root 660\r\n");
+        std::fs::write(&path, &input).unwrap();
+        let mut expected = parse_strict(INPUT).unwrap();
+        expected.insert(expected.len() - 1, common_case("sdz"));
+        assert_eq!(parse_mmap(&path).unwrap(), expected);
+
+        let labeled = "# disks\r\n  #name: disks \r\nsd[a-z] root:disk 660\r\n";
+        std::fs::write(&path, labeled).unwrap();
+        assert_eq!(parse_mmap(&path).unwrap(), parse_strict(labeled).unwrap());
+        std::fs::write(&path, "\u{FEFF}sda root:root 660\n").unwrap();
+        assert_eq!(parse_mmap(&path).unwrap()[0], common_case("sda"));
+
+        std::fs::write(&path, b"sda root:disk 660\nsd\xff root:disk 660\n").unwrap();
+        match parse_mmap(&path).unwrap_err() {
+            LoadError::Parse(err) => {
+                assert!(matches!(err.kind, ParseErrorKind::Utf8(_)));
+                assert_eq!(err.line, 2);
+                assert_eq!(err.column(), 3);
+                assert_eq!(err.source_line, "sd\u{FFFD} root:disk 660");
+            }
+            err => panic!("unexpected error {}", err),
+        }
+
+        assert!(matches!(
+            parse_mmap(&dir.join("missing")).unwrap_err(),
+            LoadError::Io(_)
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dialects() {
+        let input = "-sda root:disk 660\n--sdb root:disk 660\n-SUBSYSTEM=block;sdc root:disk 660\n";
+        let modern = parse_strict(input).unwrap();
+        assert_eq!(
+            modern,
+            [
+                Conf {
+                    stop: false,
+                    group: "disk".into(),
+                    ..common_case("sda")
+                },
+                Conf {
+                    stop: false,
+                    group: "disk".into(),
+                    ..common_case("-sdb")
+                },
+                Conf {
+                    stop: false,
+                    envmatches: vec![EnvMatch::new("SUBSYSTEM", regex("block"))],
+                    group: "disk".into(),
+                    ..common_case("sdc")
+                },
+                Conf::default(),
+            ]
+        );
+
+        let options = ParseOptions {
+            dialect: Dialect::Legacy,
+            ..Default::default()
+        };
+        let (legacy, errors) = parse_collecting_with(input, &options);
+        assert_eq!(
+            legacy[..2],
+            [
+                Conf {
+                    group: "disk".into(),
+                    ..common_case("-sda")
+                },
+                Conf {
+                    group: "disk".into(),
+                    ..common_case("--sdb")
+                },
+            ]
+        );
+        // A leading `-` is a regex, which cannot contain `;`
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 3);
+        // The canonical form can be parsed back in the legacy dialect as well
+        assert_eq!(legacy[0].to_string(), "(?:)-sda root:disk 660");
+        assert_eq!(
+            parse_strict_with(&legacy[0].to_string(), &options).unwrap()[0],
+            legacy[0]
+        );
+    }
+
+    #[test]
+    fn packed_mode() {
+        let packed = "-SUBSYSTEM=block;sd[a-z] root:disk:640 =disk/ @/bin/notify\n";
+        let res = parse_strict(packed);
+        #[cfg(feature = "extensions")]
+        {
+            let spaced = "-SUBSYSTEM=block;sd[a-z] root:disk 640 =disk/ @/bin/notify\n";
+            let conf = res.unwrap();
+            assert_eq!(conf, parse_strict(spaced).unwrap());
+            assert_eq!(format!("{}\n", conf[0]), spaced);
+            assert!(parse_strict("sda root:disk: 640").is_err());
+        }
+        #[cfg(not(feature = "extensions"))]
+        {
+            let err = res.unwrap_err();
+            assert_eq!(
+                err.kind,
+                ParseErrorKind::Extension("packing the mode with the owner")
+            );
+            assert_eq!(err.span, 25..38);
+        }
+    }
+
+    #[test]
+    fn glob() {
+        let input = "glob:sd[a-z] root:disk 660\n$DEVNAME=glob:input/* root:input 640\n";
+        let conf = parse_strict(input).unwrap();
+        let regex = |conf: &Conf| match &conf.filter {
+            Filter::DeviceRegex(v) => v.regex.clone(),
+            Filter::MajMin(_) | Filter::MajMinList(_) => unreachable!(),
+        };
+        #[cfg(feature = "extensions")]
+        {
+            assert_eq!(
+                conf[0].filter,
+                DeviceRegex::from_glob(None, "sd[a-z]").unwrap().into()
+            );
+            assert!(regex(&conf[0]).is_match("sda"));
+            assert!(!regex(&conf[0]).is_match("sdaa"));
+            assert!(regex(&conf[1]).is_match("input/event0"));
+            assert_eq!(to_config_string(&conf), input);
+            assert_ne!(
+                conf[0].filter,
+                DeviceRegex::try_new(None, "^sd[a-z]$").unwrap().into()
+            );
+        }
+        #[cfg(not(feature = "extensions"))]
+        {
+            // Without the extensions, this is a valid regex
+            assert_eq!(regex(&conf[0]).as_str(), "glob:sd[a-z]");
+            assert!(!regex(&conf[0]).is_match("sda"));
+        }
+    }
+
+    #[test]
+    fn spaced_mode() {
+        let err = parse_strict("sda root:disk 6 6  0 =disk/\n").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::SpacedMode);
+        assert_eq!(err.span, 14..20);
+
+        let options = ParseOptions {
+            coerce_spaced_mode: true,
+            ..Default::default()
+        };
+        let conf = parse_strict_with("sda root:disk 6 6  0 =disk/\n", &options).unwrap();
+        assert_eq!(conf, parse_strict("sda root:disk 660 =disk/\n").unwrap());
+        let conf = parse_strict_with("sda root:disk 660\n", &options).unwrap();
+        assert_eq!(conf[0].mode, 0o660);
+
+        let err = parse_strict_with("sda root:disk 6 9 0\n", &options).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::OctalDigit('9'));
+        assert_eq!(err.span, 16..17);
+        let conf = parse_strict_with("sda root:disk 4 7 5 5\n", &options).unwrap();
+        assert_eq!(conf[0].mode, 0o4755);
+        assert!(parse_strict_with("sda root:disk 6 6 0 0 0\n", &options).is_err());
+    }
+
+    #[test]
+    fn regex_size_limit() {
+        let input = "SUBSYSTEM=((((a{10}){10}){10}){10});sd[a-z] root:disk 660\n";
+        assert!(parse_strict(input).is_ok());
+
+        let options = ParseOptions {
+            regex_size_limit: Some(1000),
+            ..Default::default()
+        };
+        let err = parse_strict_with(input, &options).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            ParseErrorKind::Regex(regex::Error::CompiledTooBig(1000))
+        ));
+        assert_eq!(err.span, 10..35);
+        assert!(parse_strict_with("sd[a-z] root:disk 660\n", &options).is_ok());
+    }
+
+    #[test]
+    fn default_prevent() {
+        let conf = parse_with_default_prevent("sd[a-z] root:disk 660\n-.* root:root 600\n");
+        assert_eq!(conf.len(), 3);
+        assert_eq!(conf[2].to_string(), ".* root:root 660 !");
+        assert_eq!(
+            to_config_string(&conf),
+            "sd[a-z] root:disk 660\n-.* root:root 600\n.* root:root 660 !\n"
+        );
+
+        let rules = RuleSet::new(&conf);
+        let device = |name: &str| MatchContext {
+            name: name.into(),
+            devnum: None,
+            env: Default::default(),
+        };
+        assert_eq!(rules.first_match(&device("sda")), Some(&conf[0]));
+        assert_eq!(rules.first_match(&device("null")), Some(&conf[2]));
+    }
+
+    #[test]
+    fn all_or_nothing() {
+        let valid = "sda root:root 660\n# comment\n";
+        assert_eq!(parse2(valid), Ok(parse(valid)));
+
+        let errors = parse2("sda root 660\nsdb root:root 660\nsdc root:root 9\n").unwrap_err();
+        assert_eq!(
+            errors.iter().map(|err| err.line).collect::<Vec<_>>(),
+            [1, 3]
+        );
+    }
+
+    #[test]
+    fn shell_substitution_policy() {
+        let input = "sda root:disk 660 @/bin/notify $MDEV $(reboot)\n";
+        assert!(parse_strict(input).is_ok());
+
+        let options = ParseOptions {
+            shell_substitution: Policy::Deny,
+            ..Default::default()
+        };
+        let err = parse_strict_with(input, &options).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::ShellSubstitution);
+        assert_eq!(err.span, 37..39);
+        assert!(parse_strict_with("sda root:disk 660 @/bin/notify $MDEV\n", &options).is_ok());
+    }
+
+    #[test]
+    fn majmin_list() {
+        let input = "-SUBSYSTEM=block;@8,0 @8,16-31\t@65,0 root:disk 660\n";
+        let res = parse_strict(input);
+        #[cfg(feature = "extensions")]
+        {
+            let conf = res.unwrap();
+            assert_eq!(
+                conf[0].filter,
+                Filter::MajMinList(vec![
+                    MajMin::with_count(8, 0, 1),
+                    MajMin::with_count(8, 16, 16),
+                    MajMin::with_count(65, 0, 1),
+                ])
+            );
+            assert_eq!(
+                conf[0].to_string(),
+                "-SUBSYSTEM=block;@8,0 @8,16-31 @65,0 root:disk 660"
+            );
+            assert_eq!(parse_strict(&conf[0].to_string()).unwrap()[0], conf[0]);
+
+            let device = |devnum| MatchContext {
+                name: "sda".into(),
+                devnum,
+                env: [("SUBSYSTEM".to_string(), "block".to_string())].into(),
+            };
+            let matched: Vec<_> = [
+                Some((8, 0)),
+                Some((8, 1)),
+                Some((8, 20)),
+                Some((65, 0)),
+                None,
+            ]
+            .iter()
+            .map(|&devnum| conf[0].matches(&device(devnum)))
+            .collect();
+            assert_eq!(matched, [true, false, true, true, false]);
+            let rules = RuleSet::new(&conf);
+            assert_eq!(rules.first_match(&device(Some((8, 20)))), Some(&conf[0]));
+        }
+        #[cfg(not(feature = "extensions"))]
+        {
+            let err = res.unwrap_err();
+            assert_eq!(
+                err.kind,
+                ParseErrorKind::Extension("listing several majmin ranges")
+            );
+            assert_eq!(err.span, 17..36);
+        }
+    }
+
+    #[test]
+    fn hex_majmin() {
+        let res = parse_strict("@0x8,0x10-0x1f root:disk 660\n@0X41,0 root:disk 660\n");
+        #[cfg(feature = "extensions")]
+        {
+            let conf = res.unwrap();
+            assert_eq!(
+                conf[0].filter,
+                Filter::MajMin(MajMin::with_count(8, 16, 16))
+            );
+            assert_eq!(conf[1].filter, Filter::MajMin(MajMin::with_count(65, 0, 1)));
+            assert_eq!(conf[0].to_string(), "@8,16-31 root:disk 660");
+            let majmin = MajMin::with_count(8, 16, 16);
+            assert_eq!(format!("{:#}", majmin), "@0x8,0x10-0x1f");
+            assert_eq!(format!("{:#}", MajMin::with_count(65, 0, 1)), "@0x41,0x0");
+            let line = format!("{:#} root:disk 660", majmin);
+            assert_eq!(parse_strict(&line).unwrap()[0], conf[0]);
+        }
+        #[cfg(not(feature = "extensions"))]
+        {
+            let err = res.unwrap_err();
+            assert_eq!(
+                err.kind,
+                ParseErrorKind::Extension("writing device numbers in hexadecimal")
+            );
+            assert_eq!(err.span, 1..4);
+        }
+    }
+
+    #[test]
+    fn group_only() {
+        let res = parse_strict(
+            "sda :disk 660
+sdb :disk:600
+",
+        );
+        #[cfg(feature = "extensions")]
+        {
+            let conf = res.unwrap();
+            assert_eq!(conf[0].user, "root");
+            assert_eq!(conf[0].group, "disk");
+            assert_eq!(conf[1].user, "root");
+            assert_eq!(conf[1].mode, 0o600);
+            assert_eq!(conf[0].to_string(), "sda root:disk 660");
+            assert_eq!(parse_strict(&conf[0].to_string()).unwrap()[0], conf[0]);
+        }
+        #[cfg(not(feature = "extensions"))]
+        {
+            let err = res.unwrap_err();
+            assert_eq!(err.kind, ParseErrorKind::Extension("omitting the user"));
+            assert_eq!(err.span, 4..5);
+        }
+    }
+
+    #[test]
+    fn with_filter() {
+        let conf =
+            parse("-SUBSYSTEM=block;sd[a-z] root:disk 640 =disk/ @/bin/notify\n").swap_remove(0);
+        let retargeted = conf.clone().with_device_regex("hd[a-z]").unwrap();
+        assert_eq!(
+            retargeted.to_string(),
+            "-SUBSYSTEM=block;hd[a-z] root:disk 640 =disk/ @/bin/notify"
+        );
+        assert_eq!(retargeted.clone().with_filter(conf.filter.clone()), conf);
+
+        let majmin = conf
+            .clone()
+            .with_filter(Filter::MajMin(MajMin::with_count(3, 0, 64)));
+        assert_eq!(
+            majmin.to_string(),
+            "-SUBSYSTEM=block;@3,0-63 root:disk 640 =disk/ @/bin/notify"
+        );
+        assert!(conf.with_device_regex("hd[a-z").is_err());
+    }
+
+    #[test]
+    fn comment_char() {
+        let input = "\
+; disks
+  ;indented;
+# still a comment
+SUBSYSTEM=block;sd[a-z] root:disk 660
+-;sda root:disk 660
+";
+        let options = ParseOptions {
+            comment_char: ';',
+            ..Default::default()
+        };
+        let (confs, errors) = parse_collecting_with(input, &options);
+        assert_eq!(
+            confs[..1],
+            [Conf {
+                envmatches: vec![EnvMatch::new("SUBSYSTEM", regex("block"))],
+                group: "disk".into(),
+                ..common_case("sd[a-z]")
+            }]
+        );
+        // Only a leading `;` starts a comment
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 5);
+
+        let mut parser = StreamParser::with_options(options);
+        assert_eq!(
+            parser.feed("  ; disks "),
+            ParseEvent::Comment("; disks".into())
+        );
+        assert!(matches!(
+            StreamParser::new().feed("; disks"),
+            ParseEvent::Error(_)
+        ));
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let input = "\u{FEFF}sda root:root 660";
+        assert_eq!(
+            parse_strict(input).unwrap(),
+            parse_strict("sda root:root 660").unwrap()
+        );
+        assert_eq!(parse_lazy(input)[0].get().unwrap(), &common_case("sda"));
+        assert_eq!(
+            parse_with_comments("\u{FEFF}# disks\n").unwrap(),
+            [ConfLine::Comment("# disks".into())]
+        );
+        // Only at the start of the input
+        assert!(parse_strict("sda root:root 660\n\u{FEFF}sdb root:root 660").is_err());
+    }
 }
@@ -0,0 +1,749 @@
+use crate::{without_default, Conf, DeviceRegex, Filter, MajMin, OnCreation, WhenToRun};
+use regex::Regex;
+use regex_syntax::ast::{self, Ast, ClassSetBinaryOp, ClassSetItem, GroupKind, LiteralKind};
+use regex_syntax::hir::Properties;
+use std::fmt::{self, Display};
+use std::ops::Range;
+
+fn properties(regex: &Regex) -> Option<Properties> {
+    regex_syntax::parse(regex.as_str())
+        .ok()
+        .map(|hir| hir.properties().clone())
+}
+
+/// Whether `regex` cannot match any string, not even the empty one
+fn never_matches(regex: &Regex) -> bool {
+    properties(regex).is_some_and(|p| p.minimum_len().is_none())
+}
+
+/// Whether `regex` can match at most the empty string
+fn only_matches_empty(regex: &Regex) -> bool {
+    properties(regex).is_some_and(|p| p.maximum_len() == Some(0))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// How serious a [`ValidationIssue`] is
+pub enum Severity {
+    /// The rule probably does not do what was meant
+    Warning,
+    /// The rule cannot work
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A contradiction in a rule that is valid for the grammar, found by [`Conf::validate`]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    /// Description of the problem
+    pub message: String,
+}
+
+impl ValidationIssue {
+    fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {}", severity, self.message)
+    }
+}
+
+impl Conf {
+    /// Whether the rule can never match any device.
+    ///
+    /// This is a best-effort check, which detects:
+    /// - a regex that cannot match anything at all, like `[^\s\S]`, in the filter or in a
+    ///   (non negated) envmatch;
+    /// - a device name regex that can only match the empty string, like `^$`, since device names
+    ///   are never empty;
+    /// - a majmin range whose upper bound is below the lower one, like `@8,16-0`, or a list of
+    ///   such ranges only.
+    pub fn is_dead(&self) -> bool {
+        let dead_filter = match &self.filter {
+            Filter::DeviceRegex(devregex) => {
+                never_matches(&devregex.regex)
+                    || (devregex.envvar.is_none() && only_matches_empty(&devregex.regex))
+            }
+            Filter::MajMin(MajMin {
+                min,
+                min2: Some(min2),
+                ..
+            }) => min2 < min,
+            Filter::MajMin(_) => false,
+            Filter::MajMinList(list) => {
+                list.iter().all(|m| m.min2.is_some_and(|min2| min2 < m.min))
+            }
+        };
+        dead_filter
+            || self
+                .envmatches
+                .iter()
+                .any(|e| !e.negated && never_matches(&e.regex))
+    }
+
+    /// Whether the command of the rule is looked up in `$PATH`, which may not be set when mdev
+    /// runs during boot, or [`None`] if the rule has no command
+    pub fn command_is_relative(&self) -> Option<bool> {
+        self.command
+            .as_ref()
+            .map(|command| !command.path.starts_with('/'))
+    }
+
+    /// Whether the path or an argument of the command contains a command substitution: `$(` or
+    /// a backtick, not escaped with `\\`.
+    ///
+    /// mdev runs the command with `sh -c`, so a substitution runs another command whose output
+    /// becomes part of the command line. Running the command directly, like
+    /// [`Command::to_process_command`](crate::Command::to_process_command) does, is the safe
+    /// way, but such a rule is worth reviewing anyway. See also
+    /// [`ParseOptions::shell_substitution`](crate::ParseOptions::shell_substitution).
+    pub fn command_has_shell_metachars(&self) -> bool {
+        self.command.as_ref().is_some_and(|command| {
+            std::iter::once(&command.path)
+                .chain(&command.args)
+                .any(|word| shell_substitution(word).is_some())
+        })
+    }
+
+    /// Whether the move or symlink target contains a backslash, most likely written by a tool
+    /// expecting Windows-style separators. On Linux it is a literal filename character, so
+    /// `>loop\%1` creates a node named `loop\1` directly in `/dev`.
+    pub fn has_backslash_path(&self) -> bool {
+        match &self.on_creation {
+            Some(OnCreation::Move(path) | OnCreation::SymLink(path)) => path.contains('\\'),
+            _ => false,
+        }
+    }
+
+    /// Checks that the parts of the rule do not contradict each other.
+    ///
+    /// The issues found are:
+    /// - a command run after creating a node whose creation is prevented with `!`, which mdev
+    ///   runs anyway (warning);
+    /// - a `%N` in the target path referring to a group that the filter regex does not have, or
+    ///   any `%N` with a majmin filter, which is then kept as is (warning);
+    /// - an empty move or symlink target, which would replace `/dev` itself (error).
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        let after = self
+            .command
+            .as_ref()
+            .is_some_and(|c| matches!(c.when, WhenToRun::After | WhenToRun::Both));
+        if after && self.on_creation == Some(OnCreation::Prevent) {
+            issues.push(ValidationIssue::new(
+                Severity::Warning,
+                "the command runs after creating the node, but its creation is prevented",
+            ));
+        }
+
+        if let Some(OnCreation::Move(path) | OnCreation::SymLink(path)) = &self.on_creation {
+            if path.is_empty() {
+                issues.push(ValidationIssue::new(Severity::Error, "the target is empty"));
+            }
+        }
+        issues.extend(self.validate_substitutions().into_iter().map(|issue| {
+            ValidationIssue::new(
+                Severity::Warning,
+                format!(
+                    "the target refers to %{}, which the filter does not capture",
+                    issue.group
+                ),
+            )
+        }));
+        issues
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A `%N` in a target path referring to a group that the filter does not capture, found by
+/// [`Conf::validate_substitutions`]
+pub struct SubstitutionIssue {
+    /// Byte offset of the `%` in the path
+    pub position: usize,
+    /// The group referred to
+    pub group: u32,
+    /// Number of capture groups of the filter, excluding the whole match `%0`
+    pub available: usize,
+}
+
+impl Conf {
+    /// Finds the `%N` in the move or symlink target that refer to groups the filter regex does
+    /// not have. mdev keeps them as they are instead of substituting them.
+    ///
+    /// `%0`, the whole match, is always available with a regex filter, while a majmin filter
+    /// has no groups at all.
+    pub fn validate_substitutions(&self) -> Vec<SubstitutionIssue> {
+        let path = match &self.on_creation {
+            Some(OnCreation::Move(path) | OnCreation::SymLink(path)) => path,
+            _ => return Vec::new(),
+        };
+        // Number of groups including the whole match
+        let groups = match &self.filter {
+            Filter::DeviceRegex(DeviceRegex { regex, .. }) => regex.captures_len(),
+            Filter::MajMin(_) | Filter::MajMinList(_) => 0,
+        };
+        let mut issues = Vec::new();
+        let mut chars = path.char_indices().peekable();
+        while let Some((position, c)) = chars.next() {
+            let digit = chars.peek().and_then(|(_, d)| d.to_digit(10));
+            if let Some(group) = digit.filter(|_| c == '%') {
+                chars.next();
+                if group as usize >= groups {
+                    issues.push(SubstitutionIssue {
+                        position,
+                        group,
+                        available: groups.saturating_sub(1),
+                    });
+                }
+            }
+        }
+        issues
+    }
+}
+
+/// Byte range of the first command substitution in `word`, a `$(` or a backtick not escaped
+/// with `\\`
+pub(crate) fn shell_substitution(word: &str) -> Option<Range<usize>> {
+    let mut chars = word.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '`' => return Some(i..i + 1),
+            '$' if word[i + 1..].starts_with('(') => return Some(i..i + 2),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the indices of the rules that can never match, see [`Conf::is_dead`]
+pub fn dead_rules(confs: &[Conf]) -> Vec<usize> {
+    confs
+        .iter()
+        .enumerate()
+        .filter(|(_, conf)| conf.is_dead())
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Returns the indices of the rules whose command is not an absolute path, see
+/// [`Conf::command_is_relative`]
+pub fn lint_relative_commands(confs: &[Conf]) -> Vec<usize> {
+    confs
+        .iter()
+        .enumerate()
+        .filter(|(_, conf)| conf.command_is_relative() == Some(true))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A construct of a regex that busybox mdev, which uses POSIX extended regexes, does not support,
+/// or that is likely a mistake
+pub struct RegexWarning {
+    /// Index of the rule containing the regex, only known for the warnings of [`lint_regex`]
+    pub rule: Option<usize>,
+    /// Position of the construct in the pattern
+    pub span: Range<usize>,
+    pub kind: RegexWarningKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The construct found by [`validate_mdev_regex`] or [`lint_regex`]
+pub enum RegexWarningKind {
+    /// A lookahead or lookbehind, like `(?=...)`, which is not supported by this crate either
+    LookAround,
+    /// A named group, like `(?P<name>...)`
+    NamedGroup,
+    /// A non-capturing group, like `(?:...)`
+    NonCapturingGroup,
+    /// Inline flags, like `(?i)`
+    Flags,
+    /// A lazy repetition, like `*?`
+    LazyRepetition,
+    /// A Perl character class, like `\d`
+    PerlClass,
+    /// A Unicode character class, like `\pL`
+    UnicodeClass,
+    /// An operation between character classes, like `[a-z&&[^aeiou]]`
+    ClassSetOperation,
+    /// An escaped assertion, like `\b` or `\A`
+    Assertion,
+    /// An escaped character, like `\n` or `\x41`
+    Escape,
+    /// The pattern is not a valid regex at all
+    Invalid(String),
+    /// A `.` between two literal characters, like in `file.bin`, which matches any character
+    /// although a literal dot was probably meant
+    UnescapedDot,
+    /// An envmatch regex that is a plain word, possibly preceded by `^`, which also matches the
+    /// longer values containing it since envmatches are not anchored: `SUBSYSTEM=usb` matches
+    /// `usbmisc` too. The name of the variable is kept
+    UnanchoredEnvMatch(String),
+    /// A stopping rule matching every device name without any envmatch, which is not the last
+    /// rule, so that the rules after it are never reached
+    BroadWildcard,
+}
+
+impl Display for RegexWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(rule) = self.rule {
+            write!(f, "rule {}: ", rule)?;
+        }
+        let what = match &self.kind {
+            RegexWarningKind::LookAround => "look-around",
+            RegexWarningKind::NamedGroup => "named group",
+            RegexWarningKind::NonCapturingGroup => "non-capturing group",
+            RegexWarningKind::Flags => "inline flags",
+            RegexWarningKind::LazyRepetition => "lazy repetition",
+            RegexWarningKind::PerlClass => "Perl character class",
+            RegexWarningKind::UnicodeClass => "Unicode character class",
+            RegexWarningKind::ClassSetOperation => "character class operation",
+            RegexWarningKind::Assertion => "escaped assertion",
+            RegexWarningKind::Escape => "escaped character",
+            RegexWarningKind::Invalid(err) => return write!(f, "invalid regex: {}", err),
+            RegexWarningKind::UnescapedDot => {
+                return write!(
+                    f,
+                    "unescaped `.` at {}..{} matches any character",
+                    self.span.start, self.span.end
+                )
+            }
+            RegexWarningKind::UnanchoredEnvMatch(envvar) => {
+                return write!(f, "regex of {} is missing a `$` anchor", envvar)
+            }
+            RegexWarningKind::BroadWildcard => {
+                return write!(f, "regex matches every device before the last rule")
+            }
+        };
+        write!(
+            f,
+            "{} at {}..{} is not supported by mdev",
+            what, self.span.start, self.span.end
+        )
+    }
+}
+
+impl std::error::Error for RegexWarning {}
+
+struct MdevVisitor(Vec<RegexWarning>);
+
+impl MdevVisitor {
+    fn warn(&mut self, span: &ast::Span, kind: RegexWarningKind) {
+        self.0.push(RegexWarning {
+            rule: None,
+            span: span.start.offset..span.end.offset,
+            kind,
+        });
+    }
+
+    fn literal(&mut self, literal: &ast::Literal) {
+        match literal.kind {
+            LiteralKind::Verbatim | LiteralKind::Meta | LiteralKind::Superfluous => {}
+            LiteralKind::Octal
+            | LiteralKind::HexFixed(_)
+            | LiteralKind::HexBrace(_)
+            | LiteralKind::Special(_) => self.warn(&literal.span, RegexWarningKind::Escape),
+        }
+    }
+}
+
+impl ast::Visitor for MdevVisitor {
+    type Output = Vec<RegexWarning>;
+    type Err = ();
+
+    fn finish(self) -> Result<Self::Output, Self::Err> {
+        Ok(self.0)
+    }
+
+    fn visit_pre(&mut self, ast: &Ast) -> Result<(), Self::Err> {
+        match ast {
+            Ast::Flags(flags) => self.warn(&flags.span, RegexWarningKind::Flags),
+            Ast::Literal(literal) => self.literal(literal),
+            Ast::Assertion(assertion) => match assertion.kind {
+                ast::AssertionKind::StartLine | ast::AssertionKind::EndLine => {}
+                _ => self.warn(&assertion.span, RegexWarningKind::Assertion),
+            },
+            Ast::ClassUnicode(class) => self.warn(&class.span, RegexWarningKind::UnicodeClass),
+            Ast::ClassPerl(class) => self.warn(&class.span, RegexWarningKind::PerlClass),
+            Ast::Repetition(repetition) if !repetition.greedy => {
+                self.warn(&repetition.span, RegexWarningKind::LazyRepetition)
+            }
+            Ast::Group(group) => match &group.kind {
+                GroupKind::CaptureIndex(_) => {}
+                GroupKind::CaptureName { .. } => {
+                    self.warn(&group.span, RegexWarningKind::NamedGroup)
+                }
+                GroupKind::NonCapturing(flags) if flags.items.is_empty() => {
+                    self.warn(&group.span, RegexWarningKind::NonCapturingGroup)
+                }
+                GroupKind::NonCapturing(flags) => self.warn(&flags.span, RegexWarningKind::Flags),
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn visit_class_set_item_pre(&mut self, item: &ClassSetItem) -> Result<(), Self::Err> {
+        match item {
+            ClassSetItem::Literal(literal) => self.literal(literal),
+            ClassSetItem::Range(range) => {
+                self.literal(&range.start);
+                self.literal(&range.end);
+            }
+            ClassSetItem::Unicode(class) => self.warn(&class.span, RegexWarningKind::UnicodeClass),
+            ClassSetItem::Perl(class) => self.warn(&class.span, RegexWarningKind::PerlClass),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn visit_class_set_binary_op_pre(&mut self, op: &ClassSetBinaryOp) -> Result<(), Self::Err> {
+        self.warn(&op.span, RegexWarningKind::ClassSetOperation);
+        Ok(())
+    }
+}
+
+/// Checks that `pattern` only uses constructs supported by busybox mdev.
+///
+/// mdev uses POSIX extended regexes, which lack many features of the [`regex`] crate, so a
+/// pattern accepted by this crate may be rejected or behave differently under mdev. All the
+/// unsupported constructs are returned, in the order they appear in the pattern.
+pub fn validate_mdev_regex(pattern: &str) -> Result<(), Vec<RegexWarning>> {
+    let ast = ast::parse::Parser::new().parse(pattern).map_err(|err| {
+        let span = err.span().start.offset..err.span().end.offset;
+        let kind = match err.kind() {
+            ast::ErrorKind::UnsupportedLookAround => RegexWarningKind::LookAround,
+            _ => RegexWarningKind::Invalid(err.kind().to_string()),
+        };
+        vec![RegexWarning {
+            rule: None,
+            span,
+            kind,
+        }]
+    })?;
+    let warnings = ast::visit(&ast, MdevVisitor(Vec::new())).unwrap_or_default();
+    if warnings.is_empty() {
+        Ok(())
+    } else {
+        Err(warnings)
+    }
+}
+
+/// Collects the dots between two literal characters
+struct DotVisitor(Vec<Range<usize>>);
+
+impl ast::Visitor for DotVisitor {
+    type Output = Vec<Range<usize>>;
+    type Err = ();
+
+    fn finish(self) -> Result<Self::Output, Self::Err> {
+        Ok(self.0)
+    }
+
+    fn visit_pre(&mut self, ast: &Ast) -> Result<(), Self::Err> {
+        if let Ast::Concat(concat) = ast {
+            for window in concat.asts.windows(3) {
+                if let [Ast::Literal(_), Ast::Dot(span), Ast::Literal(_)] = window {
+                    self.0.push(span.start.offset..span.end.offset);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether an envmatch regex is a plain word, possibly preceded by `^` but not followed by `$`
+fn is_unanchored_word(pattern: &str) -> bool {
+    let word = pattern.strip_prefix('^').unwrap_or(pattern);
+    !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Looks for regexes in `confs` that are valid but likely not what the author meant, for
+/// linting configurations.
+///
+/// These are heuristics, so the warnings can be false positives:
+///
+/// - [`RegexWarningKind::UnescapedDot`]: a `.` between two literal characters of a filter, like
+///   `file.bin`, is probably meant to match a dot only, as `file\.bin`. A dot next to anything
+///   else, like in `.*` or `sd.[0-9]`, is left alone.
+/// - [`RegexWarningKind::UnanchoredEnvMatch`]: envmatch regexes match anywhere in the value, so a
+///   plain word like `usb` or `^usb` also selects `usbmisc`; a whole value, as in `^usb$`, was
+///   probably intended. Filter regexes are always matched against the whole name, so they are
+///   not checked.
+/// - [`RegexWarningKind::BroadWildcard`]: a stopping rule whose filter is `.*` and that has no
+///   envmatch matches every device, which only makes sense as the last, catch-all rule.
+///
+/// Filters written as globs are not checked, and neither is the trailing [`Conf::default()`].
+/// The warnings are sorted by rule, and their spans are relative to the filter regex, or to
+/// the envmatch regex for [`RegexWarningKind::UnanchoredEnvMatch`].
+pub fn lint_regex(confs: &[Conf]) -> Vec<RegexWarning> {
+    let confs = without_default(confs);
+    let mut warnings = Vec::new();
+    for (i, conf) in confs.iter().enumerate() {
+        let warn = |span, kind| RegexWarning {
+            rule: Some(i),
+            span,
+            kind,
+        };
+        if let Filter::DeviceRegex(device) = &conf.filter {
+            let pattern = device.regex.as_str();
+            if device.glob.is_none() {
+                if let Ok(ast) = ast::parse::Parser::new().parse(pattern) {
+                    let dots = ast::visit(&ast, DotVisitor(Vec::new())).unwrap_or_default();
+                    warnings.extend(
+                        dots.into_iter()
+                            .map(|span| warn(span, RegexWarningKind::UnescapedDot)),
+                    );
+                }
+            }
+            let trimmed = pattern.strip_prefix('^').unwrap_or(pattern);
+            let trimmed = trimmed.strip_suffix('$').unwrap_or(trimmed);
+            if device.envvar.is_none()
+                && trimmed == ".*"
+                && conf.envmatches.is_empty()
+                && conf.stop
+                && i + 1 < confs.len()
+            {
+                warnings.push(warn(0..pattern.len(), RegexWarningKind::BroadWildcard));
+            }
+        }
+        for envmatch in &conf.envmatches {
+            let pattern = envmatch.regex.as_str();
+            if is_unanchored_word(pattern) {
+                let kind = RegexWarningKind::UnanchoredEnvMatch(envmatch.envvar.clone());
+                warnings.push(warn(0..pattern.len(), kind));
+            }
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn dead() {
+        let conf = parse(
+            "\
+sda root:disk 660
+^$ root:root 660
+[^\\s\\S] root:root 660
+@8,16-0 root:disk 660
+@8,0-16 root:disk 660
+SUBSYSTEM=[^\\s\\S];.* root:root 660
+$DEVNAME=^$ root:root 660
+",
+        );
+        assert_eq!(dead_rules(&conf), [1, 2, 3, 5]);
+    }
+
+    #[test]
+    fn relative_commands() {
+        let conf = parse(
+            "\
+$MODALIAS=.* root:root 660 @modprobe \"$MODALIAS\"
+sda root:disk 660 */bin/mount-disk
+sdb root:disk 660
+loop([0-9]+) root:disk 660 >loop/%1 $helpers/umount-loop
+",
+        );
+        assert_eq!(conf[0].command_is_relative(), Some(true));
+        assert_eq!(conf[1].command_is_relative(), Some(false));
+        assert_eq!(conf[2].command_is_relative(), None);
+        assert_eq!(lint_relative_commands(&conf), [0, 3]);
+    }
+
+    #[test]
+    fn mdev_regex() {
+        let kinds = |pattern| {
+            validate_mdev_regex(pattern)
+                .unwrap_err()
+                .into_iter()
+                .map(|w| (w.span, w.kind))
+                .collect::<Vec<_>>()
+        };
+        validate_mdev_regex("^sd[a-z][[:digit:]]*$").unwrap();
+        validate_mdev_regex(r"(tty|pty)S?\.[0-9]{1,3}").unwrap();
+        assert_eq!(kinds(r"\bsd"), [(0..2, RegexWarningKind::Assertion)]);
+        assert_eq!(
+            kinds(r"(?P<disk>sd)(?:[a-z])\d+?"),
+            [
+                (0..12, RegexWarningKind::NamedGroup),
+                (12..21, RegexWarningKind::NonCapturingGroup),
+                (21..25, RegexWarningKind::LazyRepetition),
+                (21..23, RegexWarningKind::PerlClass),
+            ]
+        );
+        assert_eq!(
+            kinds(r"(?i)[\pL\t]"),
+            [
+                (0..4, RegexWarningKind::Flags),
+                (5..8, RegexWarningKind::UnicodeClass),
+                (8..10, RegexWarningKind::Escape),
+            ]
+        );
+        assert_eq!(kinds("sd(?=a)"), [(2..5, RegexWarningKind::LookAround)]);
+        assert!(matches!(kinds("sd(")[0].1, RegexWarningKind::Invalid(_)));
+    }
+
+    #[test]
+    fn lint() {
+        let conf = parse(
+            "\
+fw.bin root:root 600
+SUBSYSTEM=usb;.* root:root 660
+SUBSYSTEM=^net$;.* root:root 600
+-.* root:root 660 @/bin/log
+.* root:root 660
+sd.[0-9]* root:disk 660
+a.b.c root:root 600
+.* root:root 600
+",
+        );
+        let warnings: Vec<_> = lint_regex(&conf)
+            .into_iter()
+            .map(|w| (w.rule, w.span, w.kind))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                (Some(0), 2..3, RegexWarningKind::UnescapedDot),
+                (
+                    Some(1),
+                    0..3,
+                    RegexWarningKind::UnanchoredEnvMatch("SUBSYSTEM".into())
+                ),
+                (Some(4), 0..2, RegexWarningKind::BroadWildcard),
+                (Some(6), 1..2, RegexWarningKind::UnescapedDot),
+                (Some(6), 3..4, RegexWarningKind::UnescapedDot),
+            ]
+        );
+        assert_eq!(
+            lint_regex(&conf)[0].to_string(),
+            "rule 0: unescaped `.` at 2..3 matches any character"
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let conf = parse(
+            "\
+null root:root 666 ! @/bin/notify
+null root:root 666 ! $/bin/notify
+loop([0-9]+) root:disk 660 >loop/%1
+loop[0-9]+ root:disk 660 >loop/%1%0
+@8,0 root:disk 660 =disk/%0
+sda root:disk 660 =
+",
+        );
+        let issues: Vec<_> = conf
+            .iter()
+            .map(|c| {
+                c.validate()
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                vec!["warning: the command runs after creating the node, but its creation is prevented"],
+                vec![],
+                vec![],
+                vec!["warning: the target refers to %1, which the filter does not capture"],
+                vec!["warning: the target refers to %0, which the filter does not capture"],
+                vec!["error: the target is empty"],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn substitutions() {
+        let conf = parse(
+            "\
+usb/([0-9]+)-([0-9]+) root:root 660 =bus/%1/%2/%3%0
+@8,0 root:disk 660 >disk/%1
+sda root:disk 660 >%%0
+",
+        );
+        assert_eq!(
+            conf[0].validate_substitutions(),
+            [SubstitutionIssue {
+                position: 10,
+                group: 3,
+                available: 2
+            }]
+        );
+        assert_eq!(
+            conf[1].validate_substitutions(),
+            [SubstitutionIssue {
+                position: 5,
+                group: 1,
+                available: 0
+            }]
+        );
+        assert_eq!(conf[2].validate_substitutions(), []);
+        assert_eq!(conf[3].validate_substitutions(), []);
+    }
+
+    #[test]
+    fn backslash_path() {
+        let conf = parse(
+            "\
+loop([0-9]+) root:disk 660 >loop\\%1
+loop([0-9]+) root:disk 660 >loop/%1
+sda root:disk 660 @C:\\bin\\notify
+",
+        );
+        assert!(conf[0].has_backslash_path());
+        assert!(!conf[1].has_backslash_path());
+        assert!(!conf[2].has_backslash_path());
+
+        let devregex = match &conf[0].filter {
+            Filter::DeviceRegex(devregex) => devregex,
+            Filter::MajMin(_) | Filter::MajMinList(_) => unreachable!(),
+        };
+        let caps = devregex.captures("loop1");
+        let path = conf[0].target_path("loop1", caps.as_ref()).unwrap();
+        assert_eq!(path, std::path::Path::new("/dev/loop\\1"));
+        assert_eq!(path.components().count(), 3);
+    }
+
+    #[test]
+    fn shell_metachars() {
+        let conf = parse(
+            "\
+sda root:disk 660 @/bin/notify $(reboot)
+sda root:disk 660 @/bin/notify `reboot`
+sda root:disk 660 @/bin/notify \\$(reboot) \\`reboot\\` $MDEV ${ACTION}
+sda root:disk 660 @/bin/$(id)
+sda root:disk 660
+",
+        );
+        let flagged: Vec<_> = conf.iter().map(Conf::command_has_shell_metachars).collect();
+        assert_eq!(flagged, [true, true, false, true, false, false]);
+        assert_eq!(shell_substitution("a`b`"), Some(1..2));
+        assert_eq!(shell_substitution("x$(y)"), Some(1..3));
+        assert_eq!(shell_substitution("\\\\$(y)"), Some(2..4));
+        assert_eq!(shell_substitution("$"), None);
+    }
+}
@@ -0,0 +1,57 @@
+use crate::{Conf, EnvMatch, Filter};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+/// The part of a rule selecting the devices it applies to, see [`Conf::matcher_only`].
+///
+/// Like for [`Conf`], regexes are compared and hashed in their escaped form, so matchers
+/// written differently but parsed to the same regexes are equal. The hash only depends on the
+/// pattern of the regexes, so a matcher can be used as a key although [`regex::Regex`] has
+/// interior mutability.
+pub struct Matcher {
+    /// Whether the search stops at the rule, see [`Conf::stop`]
+    pub stop: bool,
+    /// The conditions on the environment, see [`Conf::envmatches`]
+    pub envmatches: Vec<EnvMatch>,
+    /// The filter on the device, see [`Conf::filter`]
+    pub filter: Filter,
+}
+
+impl Conf {
+    /// The matching part of the rule, without the owner, the mode and the actions, for grouping
+    /// the rules that target the same devices
+    pub fn matcher_only(&self) -> Matcher {
+        Matcher {
+            stop: self.stop,
+            envmatches: self.envmatches.clone(),
+            filter: self.filter.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse;
+    use std::collections::HashMap;
+
+    #[test]
+    fn grouping() {
+        let confs = parse(
+            "\
+SUBSYSTEM=sound;.* root:audio 660
+SUBSYSTEM=sound;.* root:root 600 @/bin/beep
+-SUBSYSTEM=sound;.* root:audio 660
+sd[a-z] root:disk 660
+sd[a-z] root:disk 640 =disk/
+",
+        );
+        // The interior mutability of `Regex` is a cache, which does not affect the hash
+        #[allow(clippy::mutable_key_type)]
+        let mut groups: HashMap<_, Vec<usize>> = HashMap::new();
+        for (i, conf) in confs.iter().enumerate() {
+            groups.entry(conf.matcher_only()).or_default().push(i);
+        }
+        let mut groups: Vec<_> = groups.into_values().collect();
+        groups.sort();
+        assert_eq!(groups, [vec![0, 1], vec![2], vec![3, 4], vec![5]]);
+    }
+}
@@ -0,0 +1,985 @@
+use crate::{
+    parse_strict, without_default, Conf, DeviceRegex, EnvMatch, Filter, MajMin, MatchOptions,
+    ParseError, Policy,
+};
+use regex::{Captures, Regex, RegexSet, SetMatches};
+use regex_syntax::hir::literal::{ExtractKind, Extractor};
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::warn;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// The properties of a device that rules are matched against
+pub struct MatchContext {
+    /// Name of the device, like `sda1`
+    pub name: String,
+    /// Major and minor numbers, if the device has a node
+    pub devnum: Option<(u32, u32)>,
+    /// Environment of the uevent
+    pub env: HashMap<String, String>,
+}
+
+/// Parses a `MAJOR` or `MINOR` value, in decimal or, according to `hex`, in `0x`-prefixed
+/// hexadecimal
+fn device_number(var: &str, value: &str, hex: Policy) -> Option<u32> {
+    let value = value.trim();
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(digits) => match hex {
+            Policy::Allow => u32::from_str_radix(digits, 16).ok(),
+            Policy::Warn => {
+                warn!("hexadecimal {} in uevent: {}", var, value);
+                u32::from_str_radix(digits, 16).ok()
+            }
+            Policy::Deny => None,
+        },
+        None => value.parse().ok(),
+    }
+}
+
+impl MatchContext {
+    /// Builds the context from the environment of a uevent, with the default [`MatchOptions`].
+    ///
+    /// The name is taken from `DEVNAME`, or from the last component of `DEVPATH` if missing, and
+    /// the device numbers from `MAJOR` and `MINOR`, which can be decimal or `0x`-prefixed
+    /// hexadecimal.
+    pub fn from_uevent(env: HashMap<String, String>) -> Self {
+        Self::from_uevent_with(env, &MatchOptions::default())
+    }
+
+    /// Same as [`MatchContext::from_uevent`], using the given [`MatchOptions`].
+    ///
+    /// Only [`MatchOptions::hex_device_numbers`] is used here: the values are not cut to
+    /// [`MatchOptions::max_input_len`], see [`MatchContext::truncate`].
+    pub fn from_uevent_with(env: HashMap<String, String>, options: &MatchOptions) -> Self {
+        let name = match env.get("DEVNAME") {
+            Some(name) => name.clone(),
+            None => env
+                .get("DEVPATH")
+                .and_then(|path| path.rsplit('/').next())
+                .unwrap_or_default()
+                .to_string(),
+        };
+        let number = |var| {
+            let value = env.get(var)?;
+            device_number(var, value, options.hex_device_numbers)
+        };
+        let devnum = number("MAJOR").zip(number("MINOR"));
+        Self { name, devnum, env }
+    }
+
+    /// Cuts the device name and the values of the environment to the length allowed by
+    /// `options`, see [`MatchOptions::max_input_len`]
+    pub fn truncate(&mut self, options: &MatchOptions) {
+        if let Some(max) = options.max_input_len {
+            let values = Some(&mut self.name)
+                .into_iter()
+                .chain(self.env.values_mut());
+            for value in values.filter(|value| value.len() > max) {
+                let end = (0..=max)
+                    .rev()
+                    .find(|&i| value.is_char_boundary(i))
+                    .unwrap();
+                value.truncate(end);
+            }
+        }
+    }
+
+    fn exceeds(&self, options: &MatchOptions) -> bool {
+        options.max_input_len.is_some_and(|max| {
+            Some(&self.name)
+                .into_iter()
+                .chain(self.env.values())
+                .any(|value| value.len() > max)
+        })
+    }
+}
+
+impl EnvMatch {
+    /// Whether `env` satisfies the condition.
+    ///
+    /// Like mdev, the regex can match anywhere in the value of the variable, and a missing
+    /// variable never matches. For a negated envmatch the result is inverted, so a missing
+    /// variable always satisfies it.
+    pub fn matches_env(&self, env: &HashMap<String, String>) -> bool {
+        self.matches_value(env.get(&self.envvar))
+    }
+
+    /// Whether the condition is satisfied by `value`, the value of the variable if it is set
+    fn matches_value(&self, value: Option<&String>) -> bool {
+        let matches = value.is_some_and(|value| match self.literal_prefix() {
+            Some(prefix) => value.starts_with(prefix),
+            None => self.regex.is_match(value),
+        });
+        matches != self.negated
+    }
+
+    /// If the regex is only satisfied by values starting with a literal string, returns it.
+    ///
+    /// Since the regex of an envmatch can match anywhere in the value, this only applies to
+    /// regexes anchored with `^` and made of literal characters, optionally followed by `.*`,
+    /// like `^/devices/pci.*`. For those, checking the value with [`str::starts_with`] is
+    /// equivalent to running the regex, which can be used to skip it in hot paths.
+    /// The negation of the envmatch is not taken into account.
+    pub fn literal_prefix(&self) -> Option<&str> {
+        let pattern = self.regex.as_str().strip_prefix('^')?;
+        let prefix = pattern.strip_suffix(".*").unwrap_or(pattern);
+        prefix
+            .chars()
+            .all(|c| !regex_syntax::is_meta_character(c) && !c.is_whitespace() && c != '#')
+            .then_some(prefix)
+    }
+}
+
+impl Conf {
+    /// Whether `env` satisfies all the envmatches of the rule
+    pub fn matches_env(&self, env: &HashMap<String, String>) -> bool {
+        self.envmatches.iter().all(|e| e.matches_env(env))
+    }
+
+    /// Same as [`Conf::matches_env`], with the environment split in layers by precedence, like
+    /// the variables of the uevent followed by static defaults.
+    ///
+    /// Each variable is read from the first layer setting it, so the layers do not need to be
+    /// merged beforehand. A variable is missing only if no layer sets it.
+    pub fn matches_env_layered(&self, layers: &[&HashMap<String, String>]) -> bool {
+        self.envmatches.iter().all(|e| {
+            let value = layers.iter().find_map(|layer| layer.get(&e.envvar));
+            e.matches_value(value)
+        })
+    }
+
+    /// Whether the rule applies to the device described by `ctx`.
+    ///
+    /// Like mdev, the filter regex must match the whole device name, or the whole value of the
+    /// variable for `$VAR=regex`, and a majmin filter never matches devices without numbers.
+    /// Use a [`RuleSet`] to match many devices.
+    pub fn matches(&self, ctx: &MatchContext) -> bool {
+        let filter = match &self.filter {
+            Filter::DeviceRegex(devregex) => {
+                let value = match &devregex.envvar {
+                    Some(var) => ctx.env.get(var).map(String::as_str),
+                    None => Some(ctx.name.as_str()),
+                };
+                value.is_some_and(|value| devregex.captures(value).is_some())
+            }
+            Filter::MajMin(majmin) => ctx.devnum.is_some_and(|n| majmin.contains(n)),
+            Filter::MajMinList(list) => ctx
+                .devnum
+                .is_some_and(|n| list.iter().any(|m| m.contains(n))),
+        };
+        filter && self.matches_env(&ctx.env)
+    }
+
+    /// Whether the filter of the rule matches the device node at `path`, like `/dev/sda`.
+    ///
+    /// The regex is matched against the path relative to `/dev`, which is how the kernel names
+    /// devices in subdirectories in `DEVNAME`: `/dev/input/event0` is matched as `input/event0`,
+    /// not `event0`. A relative path is taken as already relative to `/dev`, while an absolute
+    /// path outside of it never matches.
+    ///
+    /// A path carries no device numbers and no environment, so majmin and `$VAR=regex` filters
+    /// never match, and the envmatches are not checked.
+    pub fn matches_dev_path(&self, path: &Path) -> bool {
+        let name = match path.strip_prefix("/dev") {
+            Ok(name) => name,
+            Err(_) if path.is_relative() => path,
+            Err(_) => return false,
+        };
+        match (&self.filter, name.to_str()) {
+            (Filter::DeviceRegex(devregex @ DeviceRegex { envvar: None, .. }), Some(name)) => {
+                devregex.captures(name).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the rule applies to the device of a uevent, see [`MatchContext::from_uevent`]
+    pub fn matches_uevent(&self, env: &HashMap<String, String>) -> bool {
+        self.matches(&MatchContext::from_uevent(env.clone()))
+    }
+
+    /// The envmatches testing `var`, in the order they appear in the rule
+    pub fn envmatches_for<'a>(&'a self, var: &'a str) -> impl Iterator<Item = &'a EnvMatch> {
+        self.envmatches.iter().filter(move |e| e.envvar == var)
+    }
+
+    /// The variables of the environment read by [`Conf::matches`], besides the device name and
+    /// numbers: those tested by the envmatches and the one of a `$VAR=regex` filter.
+    ///
+    /// The other variables never change the outcome, so a matcher only needs to gather these.
+    pub fn decisive_env_vars(&self) -> BTreeSet<String> {
+        let filter = match &self.filter {
+            Filter::DeviceRegex(DeviceRegex {
+                envvar: Some(var), ..
+            }) => Some(var),
+            _ => None,
+        };
+        self.envmatches
+            .iter()
+            .map(|e| &e.envvar)
+            .chain(filter)
+            .cloned()
+            .collect()
+    }
+}
+
+/// `regex` modified to only match whole strings, with the same capture groups
+pub(crate) fn anchored(regex: &Regex) -> Regex {
+    Regex::new(&format!("^(?:{})$", regex.as_str()))
+        .expect("anchoring a valid regex keeps it valid")
+}
+
+impl DeviceRegex {
+    /// The regex anchored to match whole strings, compiled once and kept for the next calls.
+    ///
+    /// [`DeviceRegex::regex`] is public, so the kept regex is checked against it, and compiled
+    /// again if the regex was replaced since.
+    fn anchored_regex(&self) -> Cow<'_, Regex> {
+        let cached = self.anchored.get_or_init(|| anchored(&self.regex));
+        let source = cached
+            .as_str()
+            .strip_prefix("^(?:")
+            .and_then(|s| s.strip_suffix(")$"));
+        if source == Some(self.regex.as_str()) {
+            Cow::Borrowed(cached)
+        } else {
+            Cow::Owned(anchored(&self.regex))
+        }
+    }
+
+    /// The groups captured by the regex, if it matches the whole `name`.
+    ///
+    /// This applies to regexes matching the device name, and the result can be passed to
+    /// [`OnCreation::expand`](crate::OnCreation::expand) for `%N` substitution. A regex with an
+    /// envvar (`$VAR=regex`) matches the value of the variable instead, which must then be passed
+    /// in place of `name`.
+    pub fn captures<'t>(&self, name: &'t str) -> Option<Captures<'t>> {
+        // If the leftmost match covers the whole string, it is also the match of the anchored
+        // regex, which does not need to run
+        match self.regex.captures(name) {
+            Some(caps) if caps.get(0).unwrap().range() == (0..name.len()) => Some(caps),
+            Some(_) => self.anchored_regex().captures(name),
+            None => None,
+        }
+    }
+
+    /// The byte range of `name` matched by the regex, for showing what a rule matched.
+    ///
+    /// Since the regex must match the whole `name`, like in [`DeviceRegex::captures`], this is
+    /// either `0..name.len()` or `None`.
+    pub fn match_range(&self, name: &str) -> Option<Range<usize>> {
+        let whole = match self.regex.find(name) {
+            Some(m) => m.range() == (0..name.len()) || self.anchored_regex().is_match(name),
+            None => false,
+        };
+        whole.then_some(0..name.len())
+    }
+
+    /// The names of `names` matched by the regex as a whole, in their order, for trying a
+    /// pattern on known device names before deploying it.
+    ///
+    /// For a `$VAR=regex` filter, `names` are taken as values of the variable.
+    pub fn matching<'a>(&self, names: &'a [&'a str]) -> Vec<&'a str> {
+        let regex = self.anchored_regex();
+        names
+            .iter()
+            .copied()
+            .filter(|name| regex.is_match(name))
+            .collect()
+    }
+
+    /// The literal text that every string matched by the regex starts with, like `sd` for
+    /// `sd[a-z][0-9]*`, for indexing rules by the beginning of the names they match.
+    ///
+    /// The prefix is conservative: it is empty when the pattern starts with anything else than
+    /// literal characters, like a class or an alternation without a common beginning, and it
+    /// may be shorter than the literal part of very long patterns. Any name matched by the
+    /// regex is guaranteed to start with it.
+    pub fn literal_prefix(&self) -> String {
+        let hir = match regex_syntax::parse(self.regex.as_str()) {
+            Ok(hir) => hir,
+            Err(_) => return String::new(),
+        };
+        let seq = Extractor::new().kind(ExtractKind::Prefix).extract(&hir);
+        let prefix = seq.longest_common_prefix().unwrap_or_default();
+        // The extracted literals can be cut in the middle of a character
+        let valid = match std::str::from_utf8(prefix) {
+            Ok(prefix) => prefix,
+            Err(err) => std::str::from_utf8(&prefix[..err.valid_up_to()]).unwrap(),
+        };
+        valid.to_owned()
+    }
+
+    /// The names of the capture groups of the regex, in the order of their indices, with
+    /// [`None`] for unnamed groups.
+    ///
+    /// The first element is always [`None`], for the implicit group of the whole match, so
+    /// group `%N` of a substitution is at index `N`.
+    pub fn capture_names(&self) -> Vec<Option<String>> {
+        self.regex
+            .capture_names()
+            .map(|name| name.map(String::from))
+            .collect()
+    }
+}
+
+impl MajMin {
+    /// Whether the device numbered `maj`, `min` is matched
+    pub fn contains(&self, (maj, min): (u32, u32)) -> bool {
+        maj == self.maj && (self.min..=self.min2.unwrap_or(self.min)).contains(&min)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CompiledRule {
+    conf: Conf,
+    /// The regex of a [`Filter::DeviceRegex`], anchored to match the whole string
+    regex: Option<Regex>,
+    /// Index of the regex in [`RuleSet::names`], for device name filters
+    slot: Option<usize>,
+}
+
+impl CompiledRule {
+    fn new(conf: &Conf) -> Self {
+        let regex = match &conf.filter {
+            Filter::DeviceRegex(devregex) => Some(devregex.anchored_regex().into_owned()),
+            Filter::MajMin(_) | Filter::MajMinList(_) => None,
+        };
+        Self {
+            conf: conf.clone(),
+            regex,
+            slot: None,
+        }
+    }
+
+    /// Whether the rule matches, with `names` being the device name filters matching the name
+    /// of `ctx`, if known
+    fn matches(&self, ctx: &MatchContext, names: Option<&SetMatches>) -> bool {
+        let filter = match (&self.conf.filter, &self.regex) {
+            (Filter::DeviceRegex(DeviceRegex { envvar, .. }), Some(regex)) => match envvar {
+                Some(var) => ctx.env.get(var).is_some_and(|v| regex.is_match(v)),
+                None => match (names, self.slot) {
+                    (Some(names), Some(slot)) => names.matched(slot),
+                    _ => regex.is_match(&ctx.name),
+                },
+            },
+            (Filter::MajMin(majmin), _) => ctx.devnum.is_some_and(|n| majmin.contains(n)),
+            (Filter::MajMinList(list), _) => ctx
+                .devnum
+                .is_some_and(|n| list.iter().any(|m| m.contains(n))),
+            (Filter::DeviceRegex(_), None) => unreachable!(),
+        };
+        filter && self.conf.matches_env(&ctx.env)
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Rules prepared for matching many devices.
+///
+/// Like mdev, the rules are evaluated in order: every matching rule is applied, and the search
+/// ends at the first matching rule without the `-` prefix. The default rule appended by
+/// [`parse`](crate::parse) is left out.
+///
+/// The parsing functions always append the default rule after every rule of the file, so a
+/// catch-all written by the user, like `.* root:root 600`, is matched at its position in the
+/// file and takes precedence over the default. The default only applies to the devices that no
+/// rule matches, which [`RuleSet::first_match`] reports as [`None`].
+///
+/// [`RuleSet::new`] recognizes the default as a trailing rule equal to [`Conf::default()`].
+/// For rules that do not end with the default, like those returned by
+/// [`strip_default`](crate::strip_default), a last rule written exactly as `-.* root:root 660`
+/// would be left out instead: it behaves like the default, so only the index reported for it
+/// changes, but [`RuleSet::from_rules`] keeps it.
+pub struct RuleSet {
+    rules: Vec<CompiledRule>,
+    /// The regexes of the device name filters, to test them all at once. [`None`] if the
+    /// combined regex is too big, in which case each one is tested separately.
+    names: Option<RegexSet>,
+}
+
+impl RuleSet {
+    /// Prepares `confs` for matching, leaving out the trailing default rule appended by
+    /// [`parse`](crate::parse), if present
+    pub fn new(confs: &[Conf]) -> Self {
+        Self::from_rules(without_default(confs))
+    }
+
+    /// Prepares every rule of `confs` for matching, at its position, including a last rule equal
+    /// to [`Conf::default()`].
+    ///
+    /// This is meant for rules that do not end with the default appended by the parser, like
+    /// those returned by [`strip_default`](crate::strip_default) or a
+    /// [`StreamParser`](crate::StreamParser).
+    pub fn from_rules(confs: &[Conf]) -> Self {
+        let mut rules: Vec<_> = confs.iter().map(CompiledRule::new).collect();
+        let mut patterns = Vec::new();
+        for rule in &mut rules {
+            if let (Filter::DeviceRegex(DeviceRegex { envvar: None, .. }), Some(regex)) =
+                (&rule.conf.filter, &rule.regex)
+            {
+                rule.slot = Some(patterns.len());
+                patterns.push(regex.as_str());
+            }
+        }
+        let names = RegexSet::new(patterns).ok();
+        Self { rules, names }
+    }
+
+    /// Index of the rule that ended the search, or of the last matching rule with the `-`
+    /// prefix, see [`first_match_index`]
+    pub fn first_match_index(&self, ctx: &MatchContext) -> Option<usize> {
+        let names = self.names.as_ref().map(|set| set.matches(&ctx.name));
+        decisive_index(
+            self.rules
+                .iter()
+                .enumerate()
+                .filter(|(_, rule)| rule.matches(ctx, names.as_ref()))
+                .map(|(i, rule)| (i, &rule.conf)),
+        )
+    }
+
+    /// Same as [`RuleSet::first_match_index`], but first cuts the name and the variables of
+    /// `ctx` as set by `options`, see [`MatchOptions::max_input_len`]
+    pub fn first_match_index_with(
+        &self,
+        ctx: &MatchContext,
+        options: &MatchOptions,
+    ) -> Option<usize> {
+        let mut ctx = Cow::Borrowed(ctx);
+        if ctx.exceeds(options) {
+            ctx.to_mut().truncate(options);
+        }
+        self.first_match_index(&ctx)
+    }
+
+    /// The rule deciding how the device is created, or [`None`] if no rule matches and the
+    /// defaults apply
+    pub fn first_match(&self, ctx: &MatchContext) -> Option<&Conf> {
+        self.first_match_index(ctx).map(|i| &self.rules[i].conf)
+    }
+
+    /// Wraps the rules in an [`Arc`], to share them between threads
+    pub fn into_shared(self) -> Arc<RuleSet> {
+        Arc::new(self)
+    }
+
+    /// Parses `input` like [`parse_strict`] into a new shared set, which can replace this one.
+    ///
+    /// This set is left untouched, so on error the caller can keep using it.
+    pub fn reload(&self, input: &str) -> Result<Arc<RuleSet>, ParseError> {
+        Ok(RuleSet::new(&parse_strict(input)?).into_shared())
+    }
+}
+
+/// The index of the rule ending the search among the `matching` rules, or of the last one if
+/// none stops it
+fn decisive_index<'a>(matching: impl Iterator<Item = (usize, &'a Conf)>) -> Option<usize> {
+    let mut last = None;
+    for (i, conf) in matching {
+        last = Some(i);
+        if conf.stop {
+            break;
+        }
+    }
+    last
+}
+
+/// Index in `confs` of the rule deciding how the device described by `ctx` is created, or
+/// [`None`] if no rule matches and the defaults apply.
+///
+/// This is the first matching rule without the `-` prefix, or the last matching rule if all of
+/// them continue the search, like [`RuleSet::first_match`]. A trailing [`Conf::default()`]
+/// appended by the parser is ignored. Use a [`RuleSet`] to match many devices.
+pub fn first_match_index(confs: &[Conf], ctx: &MatchContext) -> Option<usize> {
+    decisive_index(
+        without_default(confs)
+            .iter()
+            .enumerate()
+            .filter(|(_, conf)| conf.matches(ctx)),
+    )
+}
+
+/// Every rule of `confs` whose filter and envmatches match the device described by `ctx`, in
+/// order, as if no rule stopped the search.
+///
+/// Like [`first_match_index`], a trailing [`Conf::default()`] appended by the parser is
+/// ignored.
+pub fn all_matches<'a>(confs: &'a [Conf], ctx: &MatchContext) -> Vec<&'a Conf> {
+    without_default(confs)
+        .iter()
+        .filter(|conf| conf.matches(ctx))
+        .collect()
+}
+
+/// Finds the rule deciding how each device is created, see [`RuleSet::first_match`]
+pub fn resolve_all<'a>(confs: &'a [Conf], devices: &[MatchContext]) -> Vec<Option<&'a Conf>> {
+    let rules = RuleSet::new(confs);
+    devices
+        .iter()
+        .map(|device| rules.first_match_index(device).map(|i| &confs[i]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn literal_prefix() {
+        let prefix = |pattern| {
+            EnvMatch {
+                envvar: "DEVPATH".into(),
+                regex: Regex::new(pattern).unwrap(),
+                negated: false,
+            }
+            .literal_prefix()
+            .map(String::from)
+        };
+        assert_eq!(prefix("^/devices/.*").as_deref(), Some("/devices/"));
+        assert_eq!(prefix("^/devices/pci").as_deref(), Some("/devices/pci"));
+        assert_eq!(prefix("^").as_deref(), Some(""));
+        assert_eq!(prefix(".*/net/.*"), None);
+        assert_eq!(prefix("/devices/.*"), None);
+        assert_eq!(prefix("^/dev.ces/.*"), None);
+        assert_eq!(prefix("^/devices/.*/net"), None);
+        assert_eq!(prefix("^/devices/.+"), None);
+        assert_eq!(prefix(r"^/devices\..*"), None);
+        assert_eq!(prefix("^(?i)abc"), None);
+    }
+
+    #[test]
+    fn envmatches() {
+        let mut envmatch = EnvMatch {
+            envvar: "SUBSYSTEM".into(),
+            regex: Regex::new("block").unwrap(),
+            negated: false,
+        };
+        assert!(envmatch.matches_env(&env(&[("SUBSYSTEM", "block")])));
+        assert!(envmatch.matches_env(&env(&[("SUBSYSTEM", "blockdev")])));
+        assert!(!envmatch.matches_env(&env(&[("SUBSYSTEM", "net")])));
+        assert!(!envmatch.matches_env(&env(&[])));
+
+        envmatch.regex = Regex::new("^bl.*").unwrap();
+        assert!(envmatch.matches_env(&env(&[("SUBSYSTEM", "block")])));
+        assert!(!envmatch.matches_env(&env(&[("SUBSYSTEM", "a block")])));
+        envmatch.regex = Regex::new("block").unwrap();
+
+        envmatch.negated = true;
+        assert!(!envmatch.matches_env(&env(&[("SUBSYSTEM", "block")])));
+        assert!(envmatch.matches_env(&env(&[("SUBSYSTEM", "net")])));
+        assert!(envmatch.matches_env(&env(&[])));
+
+        let conf = crate::parse("SUBSYSTEM=net;DEVPATH=.*/net/.*;.* root:root 600");
+        assert!(conf[0].matches_env(&env(&[
+            ("SUBSYSTEM", "net"),
+            ("DEVPATH", "/devices/virtual/net/lo")
+        ])));
+        assert!(!conf[0].matches_env(&env(&[("SUBSYSTEM", "net")])));
+        assert!(conf[1].matches_env(&env(&[])));
+    }
+
+    #[test]
+    fn layered_env() {
+        let conf = &crate::parse("SUBSYSTEM=net;INTERFACE=^eth;.* root:root 600")[0];
+        let uevent = env(&[("SUBSYSTEM", "net"), ("INTERFACE", "eth0")]);
+        let defaults = env(&[("SUBSYSTEM", "block"), ("INTERFACE", "wlan0")]);
+        assert!(conf.matches_env_layered(&[&uevent, &defaults]));
+        assert!(!conf.matches_env_layered(&[&defaults, &uevent]));
+
+        let partial = env(&[("INTERFACE", "eth1")]);
+        let fallback = env(&[("SUBSYSTEM", "net")]);
+        assert!(!conf.matches_env(&partial));
+        assert!(conf.matches_env_layered(&[&partial, &fallback]));
+        assert!(!conf.matches_env_layered(&[&partial]));
+        assert!(!conf.matches_env_layered(&[]));
+
+        let mut negated = conf.clone();
+        negated.envmatches.truncate(1);
+        negated.envmatches[0].negated = true;
+        assert!(negated.matches_env_layered(&[]));
+        assert!(!negated.matches_env_layered(&[&partial, &fallback]));
+    }
+
+    #[test]
+    fn dev_path() {
+        let conf = crate::parse(
+            "\
+sd[a-z] root:disk 660
+input/event[0-9]+ root:input 660
+event[0-9]+ root:input 660
+@8,0 root:disk 660
+$DEVNAME=sda root:disk 660
+SUBSYSTEM=block;sda root:disk 660
+",
+        );
+        let matches = |i: usize, path: &str| conf[i].matches_dev_path(Path::new(path));
+        assert!(matches(0, "/dev/sda"));
+        assert!(matches(0, "sda"));
+        assert!(!matches(0, "/dev/sda1"));
+        assert!(!matches(0, "/tmp/sda"));
+        assert!(!matches(0, "/dev/block/sda"));
+
+        // Nested paths are matched relative to /dev, not by their last component
+        assert!(matches(1, "/dev/input/event0"));
+        assert!(!matches(2, "/dev/input/event0"));
+        assert!(matches(2, "/dev/event0"));
+
+        assert!(!matches(3, "/dev/sda"));
+        assert!(!matches(4, "/dev/sda"));
+        assert!(matches(5, "/dev/sda"));
+    }
+
+    #[test]
+    fn decisive_env_vars() {
+        let conf = crate::parse(
+            "\
+SUBSYSTEM=block;DEVTYPE=disk;SUBSYSTEM=usb;$MODALIAS=usb:.* root:root 600
+sda root:root 600
+-ACTION=add;@8,0 root:root 600
+",
+        );
+        let vars = |conf: &Conf| conf.decisive_env_vars().into_iter().collect::<Vec<_>>();
+        assert_eq!(vars(&conf[0]), ["DEVTYPE", "MODALIAS", "SUBSYSTEM"]);
+        assert!(vars(&conf[1]).is_empty());
+        assert_eq!(vars(&conf[2]), ["ACTION"]);
+    }
+
+    #[test]
+    fn repeated_envmatches() {
+        let conf =
+            &crate::parse("DEVPATH=^/devices/;SUBSYSTEM=net;DEVPATH=/eth[0-9]$;.* root:root 600")
+                [0];
+        let envmatches: Vec<_> = conf.envmatches.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            envmatches,
+            ["DEVPATH=^/devices/", "SUBSYSTEM=net", "DEVPATH=/eth[0-9]$"]
+        );
+        let devpath: Vec<_> = conf
+            .envmatches_for("DEVPATH")
+            .map(|e| e.regex.as_str())
+            .collect();
+        assert_eq!(devpath, ["^/devices/", "/eth[0-9]$"]);
+        assert_eq!(conf.envmatches_for("MODALIAS").count(), 0);
+
+        let vars = |devpath| env(&[("SUBSYSTEM", "net"), ("DEVPATH", devpath)]);
+        assert!(conf.matches_env(&vars("/devices/virtual/net/eth0")));
+        assert!(!conf.matches_env(&vars("/devices/virtual/net/wlan0")));
+        assert!(!conf.matches_env(&vars("/sys/eth0")));
+    }
+
+    #[test]
+    fn captures() {
+        let conf = &crate::parse("loop([0-9]+)p([0-9]+) root:disk 660 >loop/%1/part%2")[0];
+        let devregex = match &conf.filter {
+            Filter::DeviceRegex(devregex) => devregex,
+            _ => unreachable!(),
+        };
+        let caps = devregex.captures("loop10p2").unwrap();
+        assert_eq!(&caps[1], "10");
+        assert_eq!(
+            conf.target_path("loop10p2", Some(&caps)).unwrap(),
+            std::path::Path::new("/dev/loop/10/part2")
+        );
+        assert!(devregex.captures("loop10").is_none());
+        assert!(devregex.captures("xloop10p2").is_none());
+
+        // The leftmost match is shorter than the whole string
+        let devregex = DeviceRegex::try_new(None, "(a)|(ab)").unwrap();
+        let caps = devregex.captures("ab").unwrap();
+        assert!(caps.get(1).is_none());
+        assert_eq!(&caps[2], "ab");
+        assert_eq!(devregex.match_range("ab"), Some(0..2));
+        assert_eq!(devregex.match_range("a"), Some(0..1));
+        assert_eq!(devregex.match_range("abc"), None);
+        assert_eq!(devregex.match_range("xa"), None);
+    }
+
+    #[test]
+    fn device_literal_prefix() {
+        let prefix = |pattern| {
+            DeviceRegex::try_new(None, pattern)
+                .unwrap()
+                .literal_prefix()
+        };
+        assert_eq!(prefix("sd[a-z][0-9]*"), "sd");
+        assert_eq!(prefix("^tty$"), "tty");
+        assert_eq!(prefix(r"fw\.bin"), "fw.bin");
+        assert_eq!(prefix("(loop|lo)[0-9]+"), "lo");
+        assert_eq!(prefix("ttyS?[0-9]"), "tty");
+        assert_eq!(prefix("dm-é"), "dm-é");
+        assert_eq!(prefix("[a-z]+"), "");
+        assert_eq!(prefix(".*"), "");
+        assert_eq!(prefix("(sda|)"), "");
+        assert_eq!(prefix("sd|hd"), "");
+        let glob = DeviceRegex::from_glob(None, "mmcblk*").unwrap();
+        assert_eq!(glob.literal_prefix(), "mmcblk");
+    }
+
+    #[test]
+    fn capture_names() {
+        let conf = crate::parse("(?P<disk>sd[a-z])([0-9]+) root:disk 660");
+        let devregex = match &conf[0].filter {
+            Filter::DeviceRegex(devregex) => devregex,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            devregex.capture_names(),
+            [None, Some("disk".to_string()), None]
+        );
+        assert_eq!(devregex.captures("sda1").unwrap()["disk"], *"sda");
+    }
+
+    #[test]
+    fn anchored_once() {
+        let mut devregex = DeviceRegex::new(None, Regex::new("sd[a-z]").unwrap());
+        assert!(devregex.captures("sda1").is_none());
+        assert!(matches!(devregex.anchored_regex(), Cow::Borrowed(_)));
+        assert!(devregex.match_range("xsda").is_none());
+
+        // The kept regex is not used once the regex is replaced
+        devregex.regex = Regex::new("sd[a-z][0-9]").unwrap();
+        assert!(devregex.captures("sda1").is_some());
+        assert!(devregex.captures("sda12").is_none());
+        let other = DeviceRegex::new(None, Regex::new("sd[a-z][0-9]").unwrap());
+        assert_eq!(devregex, other);
+    }
+
+    #[test]
+    fn matching() {
+        let devregex = DeviceRegex::new(None, Regex::new("sd[a-z]").unwrap());
+        let names = ["sda", "sda1", "hda", "sdb", "xsdc", "sdz", "tty0"];
+        assert_eq!(devregex.matching(&names), ["sda", "sdb", "sdz"]);
+        assert!(devregex.matching(&[]).is_empty());
+    }
+
+    #[test]
+    fn majmin_with_count() {
+        let majmin = MajMin::with_count(8, 16, 16);
+        assert_eq!(Filter::from(majmin.clone()).to_string(), "@8,16-31");
+        assert!(!majmin.contains((8, 15)));
+        assert!(majmin.contains((8, 16)));
+        assert!(majmin.contains((8, 31)));
+        assert!(!majmin.contains((8, 32)));
+        assert!(!majmin.contains((9, 16)));
+
+        let single = MajMin::with_count(1, 3, 1);
+        assert_eq!(Filter::from(single.clone()).to_string(), "@1,3");
+        assert!(single.contains((1, 3)));
+        assert!(!single.contains((1, 4)));
+    }
+
+    #[test]
+    fn catch_all_precedence() {
+        let device = |name: &str| MatchContext {
+            name: name.into(),
+            ..MatchContext::default()
+        };
+        let confs = crate::parse("sda root:disk 660\n.* root:root 600\n");
+        assert_eq!(confs.last(), Some(&Conf::default()));
+        let rules = RuleSet::new(&confs);
+        assert_eq!(rules.first_match_index(&device("sda")), Some(0));
+        assert_eq!(rules.first_match_index(&device("tty0")), Some(1));
+        assert_eq!(first_match_index(&confs, &device("tty0")), Some(1));
+
+        // A user rule identical to the default is kept, since the default comes after it
+        let mut confs = crate::parse("sda root:disk 660\n-.* root:root 660\n");
+        assert_eq!(confs.len(), 3);
+        let tty0 = device("tty0");
+        assert_eq!(RuleSet::new(&confs).first_match_index(&tty0), Some(1));
+        // Without the default, it is taken for the default unless every rule is kept
+        crate::strip_default(&mut confs);
+        assert_eq!(RuleSet::new(&confs).first_match_index(&tty0), None);
+        let rules = RuleSet::from_rules(&confs);
+        assert_eq!(rules.first_match_index(&device("tty0")), Some(1));
+        assert_eq!(rules.first_match_index(&device("sda")), Some(0));
+    }
+
+    #[test]
+    fn bounded_input() {
+        let rules = RuleSet::new(&crate::parse("sda+ root:disk 660\n.*z root:root 600\n"));
+        let options = MatchOptions {
+            max_input_len: Some(256),
+            ..MatchOptions::default()
+        };
+        let mut huge = MatchContext {
+            name: format!("sd{}", "a".repeat(10 << 20)),
+            env: env(&[("DEVPATH", &"é".repeat(1000))]),
+            ..MatchContext::default()
+        };
+        assert_eq!(rules.first_match_index_with(&huge, &options), Some(0));
+        huge.name.push('z');
+        assert_eq!(rules.first_match_index(&huge), Some(1));
+        // The `z` is cut away
+        assert_eq!(rules.first_match_index_with(&huge, &options), Some(0));
+
+        huge.truncate(&options);
+        assert_eq!(huge.name.len(), 256);
+        // Cut at a character boundary
+        assert_eq!(huge.env["DEVPATH"].len(), 256);
+        let odd = MatchOptions {
+            max_input_len: Some(255),
+            ..MatchOptions::default()
+        };
+        huge.truncate(&odd);
+        assert_eq!(huge.name.len(), 255);
+        assert_eq!(huge.env["DEVPATH"].len(), 254);
+
+        let short = MatchContext {
+            name: "tty0z".into(),
+            ..MatchContext::default()
+        };
+        assert_eq!(rules.first_match_index_with(&short, &options), Some(1));
+        assert_eq!(
+            rules.first_match_index_with(&short, &MatchOptions::default()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn shared() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<RuleSet>();
+
+        let mut rules = RuleSet::new(&crate::parse("sda root:disk 660")).into_shared();
+        let sda = device("sda", None, &[]);
+        let handle = {
+            let rules = Arc::clone(&rules);
+            let sda = sda.clone();
+            std::thread::spawn(move || rules.first_match(&sda).map(|c| c.mode))
+        };
+        assert_eq!(handle.join().unwrap(), Some(0o660));
+
+        assert!(rules.reload("sda root:disk 6x0").is_err());
+        rules = rules.reload("sda root:disk 600").unwrap();
+        assert_eq!(rules.first_match(&sda).map(|c| c.mode), Some(0o600));
+    }
+
+    #[test]
+    fn uevent() {
+        let conf = crate::parse("@8,0-15 root:disk 660\nSUBSYSTEM=block;sd[a-z] root:disk 640\n");
+        let uevent = |major, minor| {
+            env(&[
+                ("SUBSYSTEM", "block"),
+                ("DEVPATH", "/devices/pci0000:00/host0/block/sda"),
+                ("MAJOR", major),
+                ("MINOR", minor),
+            ])
+        };
+        for (major, minor) in [("8", "0"), ("0x8", "0"), ("8", "0xf"), ("0X8", " 0x0 ")] {
+            assert!(
+                conf[0].matches_uevent(&uevent(major, minor)),
+                "{}:{}",
+                major,
+                minor
+            );
+        }
+        assert!(!conf[0].matches_uevent(&uevent("8", "0x10")));
+        assert!(!conf[0].matches_uevent(&uevent("8", "zero")));
+        assert!(conf[1].matches_uevent(&uevent("8", "0")));
+        assert!(!conf[1].matches_uevent(&env(&[("SUBSYSTEM", "block"), ("DEVNAME", "sda1")])));
+
+        let options = MatchOptions {
+            hex_device_numbers: Policy::Deny,
+            ..Default::default()
+        };
+        let ctx = MatchContext::from_uevent_with(uevent("0x8", "0"), &options);
+        assert_eq!(ctx.name, "sda");
+        assert_eq!(ctx.devnum, None);
+        assert!(!conf[0].matches(&ctx));
+        let ctx = MatchContext::from_uevent_with(uevent("8", "1"), &options);
+        assert_eq!(ctx.devnum, Some((8, 1)));
+    }
+
+    fn device(name: &str, devnum: Option<(u32, u32)>, vars: &[(&str, &str)]) -> MatchContext {
+        MatchContext {
+            name: name.into(),
+            devnum,
+            env: env(vars),
+        }
+    }
+
+    #[test]
+    fn resolve() {
+        let conf = crate::parse(
+            "\
+-SUBSYSTEM=block;.* root:disk 640
+sd[a-z] root:disk 660
+-@8,16-31 root:root 600
+$MODALIAS=usb:.* root:root 644
+ram(0|1)? root:root 600
+",
+        );
+        let devices = [
+            device("sda", Some((8, 0)), &[("SUBSYSTEM", "block")]),
+            device("sda1", Some((8, 1)), &[("SUBSYSTEM", "block")]),
+            device("sdb1", Some((8, 17)), &[]),
+            device("null", Some((1, 3)), &[]),
+            device("null", None, &[("MODALIAS", "usb:v1D6B")]),
+            device("null", None, &[("MODALIAS", "pci:v1D6B")]),
+            device("ram1", Some((1, 1)), &[]),
+            device("ram10", Some((1, 10)), &[]),
+        ];
+        let resolved = resolve_all(&conf, &devices);
+        let lines: Vec<_> = resolved.iter().map(|c| c.map(|c| c.to_string())).collect();
+        assert_eq!(
+            lines,
+            [
+                Some("sd[a-z] root:disk 660".into()),
+                Some("-SUBSYSTEM=block;.* root:disk 640".into()),
+                Some("-@8,16-31 root:root 600".into()),
+                None,
+                Some("$MODALIAS=usb:.* root:root 644".into()),
+                None,
+                Some("ram(0|1)? root:root 600".into()),
+                None,
+            ]
+        );
+        assert!(std::ptr::eq(resolved[0].unwrap(), &conf[1]));
+        assert_eq!(RuleSet::new(&conf).first_match(&devices[0]), Some(&conf[1]));
+
+        let rules = RuleSet::new(&conf);
+        let indices: Vec<_> = devices
+            .iter()
+            .map(|device| first_match_index(&conf, device))
+            .collect();
+        assert_eq!(
+            indices,
+            [
+                Some(1),
+                Some(0),
+                Some(2),
+                None,
+                Some(3),
+                None,
+                Some(4),
+                None
+            ]
+        );
+        let all: Vec<_> = devices
+            .iter()
+            .map(|device| all_matches(&conf, device).len())
+            .collect();
+        assert_eq!(all, [2, 1, 1, 0, 1, 0, 1, 0]);
+        assert_eq!(all_matches(&conf, &devices[0]), [&conf[0], &conf[1]]);
+
+        let mut separate = rules.clone();
+        separate.names = None;
+        for (device, index) in devices.iter().zip(indices) {
+            assert_eq!(rules.first_match_index(device), index);
+            assert_eq!(separate.first_match_index(device), index);
+        }
+    }
+}
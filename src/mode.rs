@@ -0,0 +1,111 @@
+use crate::{Conf, ParseError, ParseErrorKind};
+use std::fmt::{self, Display};
+
+/// Formats a mode in octal as written in the configuration: three digits for the permission
+/// bits, like `660` or `060`, and four when the setuid, setgid or sticky bits are set, like
+/// `4755` or `1777`.
+///
+/// This is the format used by the [`Display`] implementation of [`Conf`], and it is parsed back
+/// by [`mode_from_octal_str`] as well as in a rule.
+pub fn format_mode(mode: u32) -> String {
+    OctalMode(mode).to_string()
+}
+
+/// Formats a mode as at least three octal digits, like `660`, or with a leading `0` when the
+/// alternate flag (`{:#}`) is used, like `0660`
+pub(crate) struct OctalMode(pub(crate) u32);
+
+impl Display for OctalMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "0")?;
+        }
+        write!(f, "{:03o}", self.0)
+    }
+}
+
+impl Conf {
+    /// The mode as an octal string with a leading `0`, like `0660`, as passed to helpers in the
+    /// environment
+    pub fn mode_octal_string(&self) -> String {
+        format!("{:#}", OctalMode(self.mode))
+    }
+}
+
+/// Parses a mode written in octal, with or without leading zeros, like `660` or `0660`.
+///
+/// The span of the returned error refers to `s`, and its line is 0.
+pub fn mode_from_octal_str(s: &str) -> Result<u32, ParseError> {
+    if let Some((i, c)) = s.char_indices().find(|(_, c)| c.to_digit(8).is_none()) {
+        return Err(ParseError::new(
+            i..i + c.len_utf8(),
+            ParseErrorKind::OctalDigit(c),
+        ));
+    }
+    u32::from_str_radix(s, 8)
+        .map_err(|err| ParseError::new(0..s.len(), ParseErrorKind::Number(err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn octal_strings() {
+        let conf = Conf {
+            mode: 0o600,
+            ..Conf::default()
+        };
+        assert_eq!(conf.mode_octal_string(), "0600");
+        assert_eq!(Conf::default().mode_octal_string(), "0660");
+        assert_eq!(OctalMode(0o7).to_string(), "007");
+        assert_eq!(OctalMode(0o4755).to_string(), "4755");
+
+        assert_eq!(format_mode(0o60), "060");
+        assert_eq!(format_mode(0o660), "660");
+        assert_eq!(format_mode(0o4755), "4755");
+        assert_eq!(format_mode(0o1777), "1777");
+        for mode in [0o60, 0o660, 0o4755, 0o1777] {
+            assert_eq!(mode_from_octal_str(&format_mode(mode)).unwrap(), mode);
+        }
+
+        assert_eq!(mode_from_octal_str("0660").unwrap(), 0o660);
+        assert_eq!(mode_from_octal_str("640").unwrap(), 0o640);
+        for mode in [0, 0o7, 0o600, 0o4755] {
+            let conf = Conf {
+                mode,
+                ..Conf::default()
+            };
+            assert_eq!(
+                mode_from_octal_str(&conf.mode_octal_string()).unwrap(),
+                mode
+            );
+        }
+
+        for mode in [0o4755, 0o1777] {
+            let conf = Conf {
+                mode,
+                ..Conf::default()
+            };
+            assert_eq!(crate::parse_strict(&conf.to_string()).unwrap()[0], conf);
+        }
+        let alternate = format!("sda root:root {:#}", OctalMode(0o660));
+        assert_eq!(
+            crate::parse_strict(&alternate).unwrap(),
+            crate::parse_strict("sda root:root 660").unwrap()
+        );
+        assert!(crate::parse_strict("sda root:root 06600").is_err());
+
+        let err = mode_from_octal_str("0680").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::OctalDigit('8'));
+        assert_eq!(err.span, 2..3);
+        assert!(matches!(
+            mode_from_octal_str("").unwrap_err().kind,
+            ParseErrorKind::Number(_)
+        ));
+        assert!(matches!(
+            mode_from_octal_str("+660").unwrap_err().kind,
+            ParseErrorKind::OctalDigit('+')
+        ));
+    }
+}
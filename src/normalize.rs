@@ -0,0 +1,163 @@
+use crate::matching::anchored;
+use crate::{Conf, DeviceRegex, EnvMatch, Filter, OnCreation};
+use regex::Regex;
+use regex_syntax::ast::{self, Ast, GroupKind, RepetitionKind, RepetitionRange};
+use std::ops::Range;
+
+/// Collects the spans of the `{1}` and `{1,1}` repetitions
+struct SingleRepetitions(Vec<Range<usize>>);
+
+impl ast::Visitor for SingleRepetitions {
+    type Output = Vec<Range<usize>>;
+    type Err = ();
+
+    fn finish(self) -> Result<Self::Output, Self::Err> {
+        Ok(self.0)
+    }
+
+    fn visit_pre(&mut self, ast: &Ast) -> Result<(), Self::Err> {
+        if let Ast::Repetition(repetition) = ast {
+            if let RepetitionKind::Range(
+                RepetitionRange::Exactly(1) | RepetitionRange::Bounded(1, 1),
+            ) = repetition.op.kind
+            {
+                let span = &repetition.op.span;
+                self.0.push(span.start.offset..span.end.offset);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn normalize(pattern: &str) -> String {
+    let ast = match ast::parse::Parser::new().parse(pattern) {
+        Ok(ast) => ast,
+        Err(_) => return pattern.to_string(),
+    };
+    if let Ast::Group(group) = &ast {
+        if matches!(&group.kind, GroupKind::NonCapturing(flags) if flags.items.is_empty()) {
+            let inner = group.ast.span();
+            return normalize(&pattern[inner.start.offset..inner.end.offset]);
+        }
+    }
+
+    let removed = ast::visit(&ast, SingleRepetitions(Vec::new())).unwrap_or_default();
+    let mut normalized = String::with_capacity(pattern.len());
+    let mut start = 0;
+    for span in removed {
+        normalized.push_str(&pattern[start..span.start]);
+        start = span.end;
+    }
+    normalized.push_str(&pattern[start..]);
+    normalized
+}
+
+impl DeviceRegex {
+    /// The source of the regex, rewritten so that more equivalent regexes have the same source.
+    ///
+    /// This is meant to find duplicate rules, so only rewrites that cannot change what the
+    /// regex matches, nor the numbering of its capture groups, are applied:
+    /// - a non-capturing group without flags wrapping the whole regex is removed, so `(?:sda)`
+    ///   becomes `sda`;
+    /// - repetitions matching exactly once, `{1}` and `{1,1}`, are removed, so `sd[a-z]{1}`
+    ///   becomes `sd[a-z]`.
+    ///
+    /// Capturing groups are kept, since `(sda)` and `sda` differ in what `%1` refers to.
+    pub fn normalized_source(&self) -> String {
+        normalize(self.regex.as_str())
+    }
+}
+
+impl Conf {
+    /// Turns a `$VAR=regex` filter into an envmatch on `VAR`, leaving a `.*` device filter.
+    ///
+    /// This changes the form of the rule but not what it matches: the regex of the filter must
+    /// match the whole value while envmatches can match any part of it, so it is anchored, and
+    /// both fail when `VAR` is not set. The rule is returned unchanged if its filter does not
+    /// test a variable, or if its target uses `%N`, which refers to the groups of the filter.
+    pub fn normalize_env_filter(mut self) -> Conf {
+        let substitutes = match &self.on_creation {
+            Some(OnCreation::Move(path) | OnCreation::SymLink(path)) => path
+                .split('%')
+                .skip(1)
+                .any(|s| s.starts_with(|c: char| c.is_ascii_digit())),
+            _ => false,
+        };
+        match &mut self.filter {
+            Filter::DeviceRegex(DeviceRegex { envvar, regex, .. })
+                if envvar.is_some() && !substitutes =>
+            {
+                let envvar = envvar.take().unwrap();
+                self.envmatches.push(EnvMatch::new(envvar, anchored(regex)));
+                self.filter = DeviceRegex::new(None, Regex::new(".*").unwrap()).into();
+            }
+            _ => {}
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized() {
+        let cases = [
+            ("sda", "sda"),
+            ("(?:sda)", "sda"),
+            ("(?:(?:sd[a-z]))", "sd[a-z]"),
+            ("(?:)", ""),
+            ("sd[a-z]{1}", "sd[a-z]"),
+            ("(?:a{1,1}b{1}?)", "ab"),
+            ("(sda)", "(sda)"),
+            ("(?i:sda)", "(?i:sda)"),
+            ("(?:a)|(?:b)", "(?:a)|(?:b)"),
+            ("a{2}", "a{2}"),
+            (r"\x{1}[{1}]", r"\x{1}[{1}]"),
+        ];
+        for (pattern, expected) in cases {
+            let devregex = DeviceRegex::try_new(None, pattern).unwrap();
+            assert_eq!(devregex.normalized_source(), expected, "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn env_filter() {
+        let confs = crate::parse(
+            "\
+SUBSYSTEM=usb;$MODALIAS=usb:v1D6B.* root:root 660 @/bin/notify
+$DEVNAME=bus/usb/([0-9]+)/([0-9]+) root:root 660 =usb/%1/%2
+sda root:disk 660
+",
+        );
+        let normalized = confs[0].clone().normalize_env_filter();
+        assert_eq!(
+            normalized.to_string(),
+            "SUBSYSTEM=usb;MODALIAS=^(?:usb:v1D6B.*)$;.* root:root 660 @/bin/notify"
+        );
+        assert_eq!(confs[1].clone().normalize_env_filter(), confs[1]);
+        assert_eq!(confs[2].clone().normalize_env_filter(), confs[2]);
+
+        let uevents = [
+            &[("SUBSYSTEM", "usb"), ("MODALIAS", "usb:v1D6Bp0002")][..],
+            &[("SUBSYSTEM", "usb"), ("MODALIAS", "xusb:v1D6Bp0002")],
+            &[("SUBSYSTEM", "usb")],
+            &[("SUBSYSTEM", "block"), ("MODALIAS", "usb:v1D6B")],
+        ];
+        let mut matched = Vec::new();
+        for uevent in uevents {
+            let mut env: std::collections::HashMap<_, _> = uevent
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            env.insert("DEVNAME".into(), "bus/usb/001/001".into());
+            assert_eq!(
+                normalized.matches_uevent(&env),
+                confs[0].matches_uevent(&env)
+            );
+            matched.push(normalized.matches_uevent(&env));
+        }
+        assert_eq!(matched, [true, false, false, false]);
+    }
+}
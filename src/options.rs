@@ -0,0 +1,137 @@
+use crate::ParseError;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// How to treat a suspicious but otherwise valid construct
+pub enum Policy {
+    /// Accept it silently
+    #[default]
+    Allow,
+    /// Accept it, but emit a warning
+    Warn,
+    /// Reject the line
+    Deny,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// The variant of the configuration syntax.
+///
+/// A `-` at the start of a rule is ambiguous: current versions of mdev read it as the continue
+/// flag, while very old versions have no such flag, stop at every matching rule and read the
+/// `-` as the first character of the regex. This matters for device names starting with `-`.
+pub enum Dialect {
+    /// A leading `-` is the continue flag, so `-sda` matches `sda` without stopping. A device
+    /// regex can still start with `-` after the flag, like `--sda`.
+    #[default]
+    Modern,
+    /// Every rule stops, and a leading `-` is part of the regex, so `-sda` matches `-sda`
+    Legacy,
+}
+
+#[derive(Clone, Debug)]
+/// Options to tweak the behaviour of the parser
+pub struct ParseOptions {
+    /// What to do with major or minor numbers above the limits of the kernel, see
+    /// [`MajMin::MAX_MAJOR`](crate::MajMin::MAX_MAJOR) and
+    /// [`MajMin::MAX_MINOR`](crate::MajMin::MAX_MINOR)
+    pub device_numbers: Policy,
+    /// Longest line accepted, in bytes. Longer lines are rejected before being parsed.
+    ///
+    /// There is no limit by default, since a configuration written by the system administrator
+    /// is trusted. When parsing untrusted input something like 4096 is more than enough for
+    /// any real configuration.
+    pub max_line_len: Option<usize>,
+    /// Variant of the syntax to parse
+    pub dialect: Dialect,
+    /// Whether to accept a mode with whitespace between the digits, like `6 6 0`, reading it as
+    /// if the whitespace was not there and emitting a warning. Such a mode is rejected by
+    /// default.
+    pub coerce_spaced_mode: bool,
+    /// Limit on the memory used by each compiled regex, in bytes, see
+    /// [`RegexBuilder::size_limit`](regex::RegexBuilder::size_limit). A line with a regex
+    /// exceeding it is rejected.
+    ///
+    /// Without a limit, the default of the [`regex`] crate applies, about 10 MiB per regex. Like
+    /// [`max_line_len`](Self::max_line_len), a lower limit is meant for untrusted input, where
+    /// every short line could otherwise take that much memory.
+    pub regex_size_limit: Option<usize>,
+    /// What to do with a command whose path or arguments contain a command substitution, `$(`
+    /// or a backtick not escaped with `\\`, see
+    /// [`Conf::command_has_shell_metachars`](crate::Conf::command_has_shell_metachars)
+    pub shell_substitution: Policy,
+    /// Character starting a comment line, `#` by default, for dialects writing comments like
+    /// `; disks`.
+    ///
+    /// A line is only a comment if this character is the first one after the indentation, so
+    /// the `;` separating the envmatches of a rule is not affected. Lines starting with `#` are
+    /// still comments, since mdev reads them so, and a comment starting with another character
+    /// cannot be continued on the next line nor give a label.
+    pub comment_char: char,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            device_numbers: Policy::default(),
+            max_line_len: None,
+            dialect: Dialect::default(),
+            coerce_spaced_mode: false,
+            regex_size_limit: None,
+            shell_substitution: Policy::default(),
+            comment_char: '#',
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// Options for matching devices, see
+/// [`RuleSet::first_match_index_with`](crate::RuleSet::first_match_index_with) and
+/// [`MatchContext::from_uevent_with`](crate::MatchContext::from_uevent_with)
+pub struct MatchOptions {
+    /// Longest device name and variable value matched, in bytes. Longer ones are cut at the
+    /// last character boundary within the limit before any regex runs on them.
+    ///
+    /// Regexes run in linear time, but names of untrusted devices can still be arbitrarily
+    /// long. A cut value is matched as if it was the whole one, so a rule like `sd.*` still
+    /// matches a long `sd` name while `.*z` misses one ending with `z`: this is a guard against
+    /// abuse, not a way to match such names correctly. There is no limit by default; real
+    /// device names are far below 256 bytes.
+    pub max_input_len: Option<usize>,
+    /// What to do with `0x`-prefixed hexadecimal `MAJOR` and `MINOR` values in a uevent, which
+    /// some non-standard producers send. When denied, the device is treated as having no device
+    /// numbers.
+    ///
+    /// This is a matching option rather than a [`ParseOptions`] one because the values come
+    /// from the uevent, read by
+    /// [`MatchContext::from_uevent_with`](crate::MatchContext::from_uevent_with), and never from
+    /// the configuration: the same parsed rules can then be matched against strict and lenient
+    /// uevent producers.
+    pub hex_device_numbers: Policy,
+}
+
+/// State shared while parsing a line
+pub(crate) struct Context<'a> {
+    pub(crate) options: &'a ParseOptions,
+    /// Warnings for the current line, without location
+    pub(crate) warnings: Vec<ParseError>,
+}
+
+impl<'a> Context<'a> {
+    pub(crate) fn new(options: &'a ParseOptions) -> Self {
+        Self {
+            options,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Records `err` as a warning or returns it, according to `policy`
+    pub(crate) fn check(&mut self, policy: Policy, err: ParseError) -> Result<(), ParseError> {
+        match policy {
+            Policy::Allow => Ok(()),
+            Policy::Warn => {
+                self.warnings.push(err);
+                Ok(())
+            }
+            Policy::Deny => Err(err),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{Conf, EnvMatch};
+
+/// Envmatches selecting the devices of common subsystems, for building rules in code.
+///
+/// Each preset is a single `SUBSYSTEM=` envmatch. Since envmatch regexes can match anywhere in
+/// the value, the regexes are anchored, so that for example [`FilterPreset::usb`] does not
+/// select `usbmisc` devices.
+#[derive(Clone, Copy, Debug)]
+pub struct FilterPreset;
+
+impl FilterPreset {
+    fn subsystem(name: &str) -> Vec<EnvMatch> {
+        vec![EnvMatch::try_new("SUBSYSTEM", &format!("^{}$", name)).expect("valid subsystem regex")]
+    }
+
+    /// Disks, partitions and other block devices: `SUBSYSTEM=^block$`
+    pub fn block() -> Vec<EnvMatch> {
+        Self::subsystem("block")
+    }
+
+    /// Network interfaces: `SUBSYSTEM=^net$`
+    pub fn net() -> Vec<EnvMatch> {
+        Self::subsystem("net")
+    }
+
+    /// Terminals and serial ports: `SUBSYSTEM=^tty$`
+    pub fn tty() -> Vec<EnvMatch> {
+        Self::subsystem("tty")
+    }
+
+    /// Keyboards, mice and other input devices: `SUBSYSTEM=^input$`
+    pub fn input() -> Vec<EnvMatch> {
+        Self::subsystem("input")
+    }
+
+    /// Sound cards: `SUBSYSTEM=^sound$`
+    pub fn sound() -> Vec<EnvMatch> {
+        Self::subsystem("sound")
+    }
+
+    /// USB devices and interfaces: `SUBSYSTEM=^usb$`
+    pub fn usb() -> Vec<EnvMatch> {
+        Self::subsystem("usb")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// The kind of device node, see [`Conf::device_type_hint`]
+pub enum DeviceType {
+    /// A block device, like a disk or a partition
+    Block,
+    /// A character device, like a terminal or an input device
+    Char,
+}
+
+/// Subsystems whose devices have block nodes
+const BLOCK_SUBSYSTEMS: &[&str] = &["block"];
+
+/// Subsystems whose devices have character nodes
+const CHAR_SUBSYSTEMS: &[&str] = &[
+    "tty",
+    "input",
+    "sound",
+    "usb",
+    "usbmisc",
+    "misc",
+    "mem",
+    "vc",
+    "drm",
+    "graphics",
+    "hidraw",
+    "video4linux",
+    "rtc",
+    "watchdog",
+    "i2c-dev",
+    "spidev",
+    "hwmon",
+    "dvb",
+];
+
+impl Conf {
+    /// Whether the rule targets block or character devices, guessed from its `SUBSYSTEM`
+    /// envmatches.
+    ///
+    /// This is only a best-effort hint: the regex must be a plain subsystem name, possibly
+    /// anchored like `^block$`, found in a list of well-known subsystems. [`None`] is returned
+    /// for other regexes, unknown or conflicting subsystems, and rules without a `SUBSYSTEM`
+    /// envmatch, even if their device name or numbers tell the type.
+    pub fn device_type_hint(&self) -> Option<DeviceType> {
+        let mut types = self
+            .envmatches
+            .iter()
+            .filter(|e| e.envvar == "SUBSYSTEM" && !e.negated)
+            .map(|e| {
+                let pattern = e.regex.as_str();
+                let name = pattern.strip_prefix('^').unwrap_or(pattern);
+                let name = name.strip_suffix('$').unwrap_or(name);
+                if BLOCK_SUBSYSTEMS.contains(&name) {
+                    Some(DeviceType::Block)
+                } else if CHAR_SUBSYSTEMS.contains(&name) {
+                    Some(DeviceType::Char)
+                } else {
+                    None
+                }
+            });
+        let first = types.next()??;
+        types.all(|t| t == Some(first)).then_some(first)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use std::collections::HashMap;
+
+    #[test]
+    fn presets() {
+        let presets = [
+            (FilterPreset::block(), "SUBSYSTEM=^block$"),
+            (FilterPreset::net(), "SUBSYSTEM=^net$"),
+            (FilterPreset::tty(), "SUBSYSTEM=^tty$"),
+            (FilterPreset::input(), "SUBSYSTEM=^input$"),
+            (FilterPreset::sound(), "SUBSYSTEM=^sound$"),
+            (FilterPreset::usb(), "SUBSYSTEM=^usb$"),
+        ];
+        for (envmatches, expected) in presets {
+            let formatted: Vec<_> = envmatches.iter().map(ToString::to_string).collect();
+            assert_eq!(formatted, [expected]);
+        }
+
+        let conf = Conf {
+            envmatches: FilterPreset::usb(),
+            ..Conf::default()
+        };
+        let env = |subsystem: &str| HashMap::from([("SUBSYSTEM".into(), subsystem.into())]);
+        assert!(conf.matches_env(&env("usb")));
+        assert!(!conf.matches_env(&env("usbmisc")));
+    }
+
+    #[test]
+    fn device_type_hint() {
+        let conf = parse(
+            "\
+SUBSYSTEM=block;.* root:disk 660
+SUBSYSTEM=^tty$;.* root:tty 660
+SUBSYSTEM=block;SUBSYSTEM=^block$;.* root:disk 660
+SUBSYSTEM=block;SUBSYSTEM=tty;.* root:disk 660
+SUBSYSTEM=net;.* root:root 600
+SUBSYSTEM=bl.*;.* root:disk 660
+DEVTYPE=disk;sd[a-z] root:disk 660
+",
+        );
+        let hints: Vec<_> = conf.iter().map(Conf::device_type_hint).collect();
+        assert_eq!(
+            hints,
+            [
+                Some(DeviceType::Block),
+                Some(DeviceType::Char),
+                Some(DeviceType::Block),
+                None,
+                None,
+                None,
+                None,
+                None,
+            ]
+        );
+        let preset = Conf {
+            envmatches: FilterPreset::input(),
+            ..Conf::default()
+        };
+        assert_eq!(preset.device_type_hint(), Some(DeviceType::Char));
+    }
+}
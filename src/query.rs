@@ -0,0 +1,87 @@
+use crate::{without_default, Conf};
+use std::collections::BTreeSet;
+
+/// Returns every rule whose command runs exactly the executable at `path`.
+///
+/// The comparison is made on the path as written in the configuration, so a bare name like
+/// `modprobe` only matches rules running `modprobe` and not `/sbin/modprobe`.
+pub fn rules_invoking<'a>(confs: &'a [Conf], path: &str) -> Vec<&'a Conf> {
+    rules_with_command(confs, |command| command == path)
+}
+
+/// Same as [`rules_invoking`], but returns the rules whose command path starts with `prefix`,
+/// e.g. every helper inside `/opt/mdev/helpers/`.
+pub fn rules_invoking_under<'a>(confs: &'a [Conf], prefix: &str) -> Vec<&'a Conf> {
+    rules_with_command(confs, |command| command.starts_with(prefix))
+}
+
+fn rules_with_command(confs: &[Conf], f: impl Fn(&str) -> bool) -> Vec<&Conf> {
+    confs
+        .iter()
+        .filter(|conf| conf.command.as_ref().is_some_and(|c| f(&c.path)))
+        .collect()
+}
+
+/// Returns the users owning the nodes created by the rules, for making sure they exist before
+/// mdev runs.
+///
+/// Names are returned as they are. The grammar only accepts letters, but a rule built or
+/// edited in code can hold a numeric id like `1000`, which is returned unchanged: resolving it
+/// is up to the caller. The trailing [`Conf::default()`] appended by [`parse`](crate::parse)
+/// is left out.
+pub fn referenced_users(confs: &[Conf]) -> BTreeSet<String> {
+    without_default(confs)
+        .iter()
+        .map(|conf| conf.user.clone())
+        .collect()
+}
+
+/// Same as [`referenced_users`], for the groups owning the nodes
+pub fn referenced_groups(confs: &[Conf]) -> BTreeSet<String> {
+    without_default(confs)
+        .iter()
+        .map(|conf| conf.group.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    const INPUT: &str = "\
+SUBSYSTEM=usb;DEVTYPE=usb_device;.* root:root 660 */opt/mdev/helpers/dev-bus-usb
+-SUBSYSTEM=net;.* root:root 600 @/opt/mdev/helpers/settle-nics --write-mactab
+$MODALIAS=.* root:root 660 @modprobe -b \"$MODALIAS\"
+sda root:disk 660
+";
+
+    #[test]
+    fn invoking() {
+        let conf = parse(INPUT);
+        let found = rules_invoking(&conf, "/opt/mdev/helpers/dev-bus-usb");
+        assert_eq!(found, [&conf[0]]);
+        assert_eq!(rules_invoking(&conf, "modprobe"), [&conf[2]]);
+        assert!(rules_invoking(&conf, "/sbin/modprobe").is_empty());
+        assert!(rules_invoking(&conf, "/opt/mdev/helpers").is_empty());
+
+        let found = rules_invoking_under(&conf, "/opt/mdev/helpers/");
+        assert_eq!(found, [&conf[0], &conf[1]]);
+    }
+
+    #[test]
+    fn owners() {
+        let mut conf = parse(INPUT);
+        conf.insert(0, parse("tty[0-9]+ daemon:tty 620")[0].clone());
+        assert_eq!(
+            referenced_users(&conf),
+            BTreeSet::from(["daemon".into(), "root".into()])
+        );
+        conf[1].group = "1000".into();
+        assert_eq!(
+            referenced_groups(&conf),
+            BTreeSet::from(["1000".into(), "disk".into(), "root".into(), "tty".into()])
+        );
+        assert!(referenced_users(&parse("")).is_empty());
+    }
+}
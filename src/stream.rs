@@ -0,0 +1,118 @@
+use crate::{
+    continuation::Continuation, label::Labels, parse_event, Conf, ParseError, ParseOptions,
+};
+
+/// Byte order mark that some editors write at the start of UTF-8 files, which is ignored
+pub(crate) const BOM: &str = "\u{feff}";
+
+#[derive(Clone, Debug, PartialEq)]
+/// What a line of the configuration contains
+// Rules are the common case, boxing them would only add an allocation per line
+#[allow(clippy::large_enum_variant)]
+pub enum ParseEvent {
+    /// A valid rule
+    Rule(Conf),
+    /// A comment, including the leading `#` or
+    /// [`comment_char`](crate::ParseOptions::comment_char)
+    Comment(String),
+    /// A line containing only whitespace
+    Blank,
+    /// A line that could not be parsed
+    Error(ParseError),
+    /// A line ending with `\`, whose rule continues on the next line. The rule is returned when
+    /// its last line is fed. Continuing lines requires the `extensions` feature
+    Continued,
+}
+
+#[derive(Clone, Debug, Default)]
+/// A parser fed one line at a time, for example while the configuration is being edited.
+///
+/// Unlike [`parse`](crate::parse), no default rule is appended at the end.
+pub struct StreamParser {
+    options: ParseOptions,
+    line_number: usize,
+    labels: Labels,
+    continuation: Continuation,
+}
+
+impl StreamParser {
+    /// Creates a parser using the default [`ParseOptions`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a parser using the given [`ParseOptions`]
+    pub fn with_options(options: ParseOptions) -> Self {
+        Self {
+            options,
+            line_number: 0,
+            labels: Labels::default(),
+            continuation: Continuation::default(),
+        }
+    }
+
+    /// Number of lines fed so far
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    /// Parses the next line of the configuration.
+    ///
+    /// The errors in a rule wrapped across several lines are reported at its first line. A byte
+    /// order mark at the start of the first line is skipped.
+    pub fn feed(&mut self, line: &str) -> ParseEvent {
+        self.line_number += 1;
+        let line = match self.line_number {
+            1 => line.strip_prefix(BOM).unwrap_or(line),
+            _ => line,
+        };
+        match self
+            .continuation
+            .push(self.line_number, line, self.options.comment_char)
+        {
+            Some((line_number, line)) => self.parse(line_number, &line),
+            None => ParseEvent::Continued,
+        }
+    }
+
+    /// Parses the rule left unfinished by a last line ending with `\`, if any, once the whole
+    /// configuration has been fed
+    pub fn finish(&mut self) -> Option<ParseEvent> {
+        let (line_number, line) = self.continuation.finish()?;
+        Some(self.parse(line_number, &line))
+    }
+
+    fn parse(&mut self, line_number: usize, line: &str) -> ParseEvent {
+        let mut event = parse_event(line_number, line, &self.options);
+        self.labels.apply(&mut event);
+        event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ParseErrorKind;
+
+    #[test]
+    fn line_by_line() {
+        let mut parser = StreamParser::new();
+        assert_eq!(
+            parser.feed("# block devices"),
+            ParseEvent::Comment("# block devices".into())
+        );
+        assert_eq!(parser.feed(" \t"), ParseEvent::Blank);
+        match parser.feed("sd[a-z] root:disk 660") {
+            ParseEvent::Rule(conf) => assert_eq!(conf.to_string(), "sd[a-z] root:disk 660"),
+            other => panic!("unexpected event {:?}", other),
+        }
+        match parser.feed("sd[a-z root:disk 660") {
+            ParseEvent::Error(err) => {
+                assert_eq!(err.line, 4);
+                assert!(matches!(err.kind, ParseErrorKind::Regex(_)));
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert_eq!(parser.line_number(), 4);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{mode::OctalMode, without_default, Conf};
+
+/// Widest a column grows: longer cells overflow instead of widening the whole column
+const MAX_WIDTH: usize = 40;
+/// Spaces between two columns
+const GAP: usize = 2;
+
+const HEADER: [&str; 5] = ["FILTER", "OWNER", "MODE", "ACTION", "COMMAND"];
+
+fn cells(conf: &Conf) -> [String; 5] {
+    let mut filter = String::from(if conf.stop { "" } else { "-" });
+    for envmatch in &conf.envmatches {
+        filter.push_str(&format!("{};", envmatch));
+    }
+    filter.push_str(&conf.filter.to_string());
+    [
+        filter,
+        format!("{}:{}", conf.user, conf.group),
+        OctalMode(conf.mode).to_string(),
+        conf.on_creation
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default(),
+        conf.command
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default(),
+    ]
+}
+
+/// Formats the rules as a table with aligned columns, for reviewing a configuration.
+///
+/// The columns are the matcher, with the `-` prefix and the envmatches, the owner, the mode, the
+/// action on creation and the command, under a header line. This is a report, not the
+/// configuration syntax. Cells are never truncated: a cell wider than 40 characters overflows
+/// its column and the rest of the row continues on the next line, aligned with the other rows.
+/// The trailing [`Conf::default()`] appended by [`parse`](crate::parse) is left out.
+pub fn format_table(confs: &[Conf]) -> String {
+    let header = HEADER.map(String::from);
+    let rows: Vec<_> = Some(header)
+        .into_iter()
+        .chain(without_default(confs).iter().map(cells))
+        .collect();
+
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            let len = cell.chars().count();
+            if len <= MAX_WIDTH {
+                *width = (*width).max(len);
+            }
+        }
+    }
+
+    let mut table = String::new();
+    for row in &rows {
+        let mut line = String::new();
+        let mut column_start = 0;
+        for (i, cell) in row.iter().enumerate() {
+            line.push_str(cell);
+            if i + 1 == row.len() {
+                break;
+            }
+            column_start += widths[i] + GAP;
+            let len = line.chars().count();
+            if len + GAP > column_start && !row[i + 1..].iter().all(String::is_empty) {
+                table.push_str(line.trim_end());
+                table.push('\n');
+                line.clear();
+            }
+            let len = line.chars().count();
+            line.push_str(&" ".repeat(column_start.saturating_sub(len)));
+        }
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn table() {
+        let confs = parse(
+            "\
+sd[a-z].* root:disk 660 =disk/
+-SUBSYSTEM=net;.* root:root 600 @/sbin/settle-nics --write-mactab
+SUBSYSTEM=usb;DEVTYPE=usb_device;DEVPATH=.*/usb[0-9]+/.*;.* root:usb 660 !
+null root:root 666
+tty[0-9]+ root:tty 620 =terminals/virtual/consoles/of/the/system/%0
+",
+        );
+        assert_eq!(
+            format_table(&confs),
+            "\
+FILTER             OWNER      MODE  ACTION  COMMAND
+sd[a-z].*          root:disk  660   =disk/
+-SUBSYSTEM=net;.*  root:root  600           @/sbin/settle-nics --write-mactab
+SUBSYSTEM=usb;DEVTYPE=usb_device;DEVPATH=.*/usb[0-9]+/.*;.*
+                   root:usb   660   !
+null               root:root  666
+tty[0-9]+          root:tty   620   =terminals/virtual/consoles/of/the/system/%0
+"
+        );
+        assert_eq!(format_table(&[]), "FILTER  OWNER  MODE  ACTION  COMMAND\n");
+    }
+}
@@ -0,0 +1,40 @@
+//! Concise constructors for the rules expected in tests, enabled by the `test-util` feature.
+
+use crate::{Conf, DeviceRegex, MajMin, DEFAULT_MODE};
+use regex::Regex;
+
+impl Conf {
+    /// A rule matching the device names that match `regex`, written
+    /// `regex user:group mode`.
+    ///
+    /// # Panics
+    ///
+    /// If `regex` is not a valid regex.
+    pub fn device(regex: &str, user: &str, group: &str, mode: u32) -> Self {
+        let regex = Regex::new(regex).expect("invalid regex");
+        Self {
+            stop: true,
+            filter: DeviceRegex::new(None, regex).into(),
+            user: user.into(),
+            group: group.into(),
+            mode,
+            ..Self::default()
+        }
+    }
+
+    /// A rule matching the device numbered `maj`, `min` with the default owner and mode,
+    /// written `@maj,min root:root 660`
+    pub fn majmin(maj: u32, min: u32) -> Self {
+        Self {
+            stop: true,
+            filter: MajMin {
+                maj,
+                min,
+                min2: None,
+            }
+            .into(),
+            mode: DEFAULT_MODE,
+            ..Self::default()
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! The special variables that mdev passes to the commands of the rules.
+
+use crate::{expand::name_len, Conf, DeviceRegex, Filter};
+
+/// The variables set by mdev for the commands it runs, besides the environment of the uevent:
+/// - `MDEV`, the name of the device node;
+/// - `ACTION`, `add` or `remove`;
+/// - `SUBSYSTEM`, the subsystem of the device;
+/// - `DEVPATH`, the path of the device in sysfs, without `/sys`.
+///
+/// The last three come from the uevent, but mdev also sets them when scanning `/sys` with `-s`.
+pub const MDEV_VARS: &[&str] = &["MDEV", "ACTION", "SUBSYSTEM", "DEVPATH"];
+
+/// The names of the variables referenced as `$VAR` or `${VAR...}` in `s`, including those
+/// nested in the default or alternative value of another one
+fn referenced_vars(s: &str) -> impl Iterator<Item = &str> {
+    s.match_indices('$').filter_map(move |(i, _)| {
+        let rest = &s[i + 1..];
+        let rest = rest.strip_prefix('{').unwrap_or(rest);
+        Some(&rest[..name_len(rest)]).filter(|name| !name.is_empty())
+    })
+}
+
+impl Conf {
+    /// The variables of [`MDEV_VARS`] read by the rule, in the order of the constant: those
+    /// tested by the envmatches or by a `$VAR=regex` filter, and those referenced in the path or
+    /// the arguments of the command.
+    ///
+    /// Tooling can compare this with the variables a helper script expects.
+    pub fn mdev_vars(&self) -> Vec<&'static str> {
+        let filter = match &self.filter {
+            Filter::DeviceRegex(DeviceRegex {
+                envvar: Some(var), ..
+            }) => Some(var.as_str()),
+            _ => None,
+        };
+        let command = self.command.iter().flat_map(|command| {
+            std::iter::once(&command.path)
+                .chain(&command.args)
+                .flat_map(|s| referenced_vars(s))
+        });
+        let read: Vec<&str> = self
+            .envmatches
+            .iter()
+            .map(|e| e.envvar.as_str())
+            .chain(filter)
+            .chain(command)
+            .collect();
+        MDEV_VARS
+            .iter()
+            .copied()
+            .filter(|var| read.contains(var))
+            .collect()
+    }
+
+    /// Whether the rule reads any of the variables of [`MDEV_VARS`], see [`Conf::mdev_vars`]
+    pub fn uses_mdev_var(&self) -> bool {
+        !self.mdev_vars().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn mdev_vars() {
+        let conf = parse(
+            "\
+SUBSYSTEM=net;.* root:root 600 @/lib/mdev/net $MDEV ${ACTION:-add}
+$DEVPATH=/devices/.* root:root 600 */lib/mdev/${MODALIAS:+${SUBSYSTEM}}
+sda root:disk 660 @/bin/log $MODALIAS $$ ${1} $MDEVICE
+sdb root:disk 660
+",
+        );
+        assert_eq!(conf[0].mdev_vars(), ["MDEV", "ACTION", "SUBSYSTEM"]);
+        assert_eq!(conf[1].mdev_vars(), ["SUBSYSTEM", "DEVPATH"]);
+        assert!(conf[0].uses_mdev_var());
+        assert!(conf[2].mdev_vars().is_empty());
+        assert!(!conf[2].uses_mdev_var());
+        assert!(!conf[3].uses_mdev_var());
+
+        let names: Vec<_> = referenced_vars("$A ${B:-$C} $ $1 ${D}x").collect();
+        assert_eq!(names, ["A", "B", "C", "D"]);
+    }
+}